@@ -1,30 +1,93 @@
 //! Downloads and runs BuildTools.
 
 use std::{fs, io};
-use std::io::ErrorKind;
-use crate::download_url;
+use std::io::{ErrorKind, Read};
+use crate::error::{Error, Result};
+use crate::http::{download_verified, Integrity};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Child, Command};
+use std::time::Duration;
 use regex::Regex;
-use crate::util::dir;
+use crate::jar::open_jar;
+use crate::util::{dir, sha1};
+use proc_macros::serial_snake;
 
 /// The URL of the latest BuildTools JAR build from SpigotMC's Jenkins.
 pub const VANILLA_JAR_REGEX: &str = r"(minecraft_)?server.(1\.\d{1,2}(?:\.\d{1,2})?)\.jar";
 const BUILDTOOLS_URL: &str = "https://hub.spigotmc.org/jenkins/job/BuildTools/lastSuccessfulBuild/artifact/target/BuildTools.jar";
 const SPIGOT_JAR_REGEX: &str = r"spigot-(1\.\d{1,2}(?:\.\d{1,2})?)\.jar";
+/// How often a running BuildTools is checked for having exited.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A child process that is killed when dropped before it exited, such as when
+/// the build waiting for it is cancelled.
+struct KillOnDrop(Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        if let Ok(None) = self.0.try_wait() {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+}
 
 /// Downloads the latest BuildTools from Spigot Jenkins.
 ///
 /// # Arguments
 ///
 /// * `path` - The path the JAR should be saved to.
-///
-/// # Panics
-///
-/// The iterator over the response stream will panic when it fails
-/// to read bytes or write them to the JAR file. 
-pub async fn download_buildtools<P: AsRef<Path>>(path: P) -> Result<(), reqwest::Error> {
-    download_url(BUILDTOOLS_URL, path).await
+/// * `integrity` - The expected hash or size of the JAR, for example a pinned SHA-1.
+pub async fn download_buildtools<P: AsRef<Path>>(path: P, integrity: &Integrity) -> Result<()> {
+    download_verified(BUILDTOOLS_URL, path, integrity).await
+}
+
+/// The identity of a BuildTools JAR.
+#[serial_snake]
+#[derive(Default)]
+pub struct BuildToolsInfo {
+    /// The SHA-1 hash of the JAR.
+    pub sha1: String,
+    /// The `Implementation-Version` of the JAR, such as `git-BuildTools-3ab4b4c-186`.
+    pub version: Option<String>,
+    /// The Jenkins build number, the last part of `version`.
+    pub build: Option<u32>,
+}
+
+impl BuildToolsInfo {
+    /// Hashes a BuildTools JAR and reads its version from its manifest.
+    pub fn read<P: AsRef<Path>>(buildtools_jar: P) -> Result<Self> {
+        let buildtools_jar = buildtools_jar.as_ref();
+        let mut zip = open_jar(buildtools_jar)?;
+
+        let mut manifest = String::new();
+        if let Ok(mut file) = zip.by_name("META-INF/MANIFEST.MF") {
+            file.read_to_string(&mut manifest)
+                .map_err(|e| Error::jar(buildtools_jar, format!("META-INF/MANIFEST.MF: {e}")))?;
+        }
+        let version = manifest
+            .lines()
+            .find_map(|line| line.strip_prefix("Implementation-Version:"))
+            .map(|version| version.trim().to_owned());
+        let build = version
+            .as_deref()
+            .and_then(|version| version.rsplit('-').next())
+            .and_then(|build| build.parse().ok());
+
+        Ok(Self {
+            sha1: sha1(buildtools_jar)?,
+            version,
+            build,
+        })
+    }
+}
+
+/// The result of running BuildTools.
+pub struct BuildToolsRun {
+    /// The generated Spigot JAR.
+    pub spigot_jar: PathBuf,
+    /// The arguments BuildTools was run with, after `java`.
+    pub args: Vec<String>,
 }
 
 /// Runs the BuildTools JAR and generates a SpigotMC JAR.
@@ -34,44 +97,69 @@ pub async fn download_buildtools<P: AsRef<Path>>(path: P) -> Result<(), reqwest:
 /// * `java_home` - The directory of the `JAVA_HOME` environment variable.
 /// * `buildtools_jar` - The path of the BuildTools JAR file.
 /// * `working_dir` - The directory where BuildTools should be run.
-///                   It is not recommended for this to be the same directory as the `buildtools_jar`
+///   It is not recommended for this to be the same directory as the `buildtools_jar`
 /// * `version` - The Minecraft version which should be used.
+/// * `offline` - Whether to reuse the existing `working_dir` as is and tell BuildTools
+///   not to pull updates.
 ///
 /// # Returns
 ///
-/// This function returns the Path of the generated Spigot JAR.
-pub async fn run_buildtools<P: AsRef<Path>>(java_home: P, buildtools_jar: P, working_dir: P, version: &str) -> io::Result<PathBuf> {
-    if working_dir.as_ref().exists() && working_dir.as_ref().is_dir() {
-        dir::clear_directory(&working_dir)
-            .await
-            .map_err(|e| io::Error::new(ErrorKind::Other, format!("Failed to clear directory: {}", e)))?;
+/// This function returns the Path of the generated Spigot JAR and the arguments used.
+pub async fn run_buildtools<P: AsRef<Path>>(java_home: P, buildtools_jar: P, working_dir: P, version: &str, offline: bool) -> Result<BuildToolsRun> {
+    let working_dir = working_dir.as_ref();
+
+    if offline {
+        if !working_dir.is_dir() {
+            return Err(Error::build_tools(
+                version,
+                format!("offline mode needs an existing working directory at {}", working_dir.display()),
+            ));
+        }
+    } else if working_dir.exists() && working_dir.is_dir() {
+        dir::clear_directory(working_dir).await?;
     } else {
-        fs::create_dir(&working_dir)
-            .map_err(|e| io::Error::new(ErrorKind::Other, format!("Failed to create directory: {}", e)))?;
+        fs::create_dir(working_dir).map_err(Error::io(working_dir))?;
     }
 
     let java_bin = java_home.as_ref().join(Path::new("bin/java"));
     let buildtools_jar_path = buildtools_jar.as_ref().to_str().ok_or_else(|| {
-        io::Error::new(ErrorKind::InvalidInput, "Invalid BuildTools JAR path")
+        Error::build_tools(version, format!("invalid BuildTools JAR path {}", buildtools_jar.as_ref().display()))
     })?;
 
-    let mut command = Command::new(java_bin);
+    let mut command = Command::new(&java_bin);
     command.stdout(io::stdout());
     command.stderr(io::stderr());
-    command.current_dir(&working_dir);
-    command.args(["-jar", buildtools_jar_path, "--rev", version]);
+    command.current_dir(working_dir);
+    let mut args = vec!["-jar", buildtools_jar_path, "--rev", version];
+    if offline {
+        args.push("--dont-update");
+    }
+    command.args(&args);
 
-    let mut process = command.spawn()?;
-    let exit_status = process.wait()?;
+    let mut process = command
+        .spawn()
+        .map(KillOnDrop)
+        .map_err(|e| Error::build_tools(version, format!("failed starting {}: {e}", java_bin.display())))?;
+    // Waiting without blocking lets the build be cancelled, which kills BuildTools.
+    let exit_status = loop {
+        match process.0.try_wait() {
+            Ok(Some(exit_status)) => break exit_status,
+            Ok(None) => tokio::time::sleep(EXIT_POLL_INTERVAL).await,
+            Err(e) => return Err(Error::build_tools(version, format!("failed waiting for BuildTools: {e}"))),
+        }
+    };
 
     if exit_status.success() {
-        let file_regex = Regex::new(SPIGOT_JAR_REGEX).unwrap();
-        find_file(&file_regex, &working_dir).await
+        let file_regex = Regex::new(SPIGOT_JAR_REGEX).map_err(|e| Error::build_tools(version, e))?;
+        Ok(BuildToolsRun {
+            spigot_jar: find_file(&file_regex, working_dir).await?,
+            args: args.into_iter().map(str::to_owned).collect(),
+        })
     } else {
         let error_code = exit_status.code().unwrap_or(-1);
-        Err(io::Error::new(
-            ErrorKind::Other,
-            format!("Failed to run BuildTools with exit code {}", error_code),
+        Err(Error::build_tools(
+            version,
+            format!("exited with code {error_code}"),
         ))
     }
 }
@@ -86,15 +174,16 @@ pub async fn run_buildtools<P: AsRef<Path>>(java_home: P, buildtools_jar: P, wor
 /// # Returns
 ///
 /// The first file that matches the given regex
-pub async fn find_file<P: AsRef<Path>>(regex: &Regex, directory: P) -> io::Result<PathBuf> {
-    for file in fs::read_dir(directory.as_ref())? {
-        let unwrapped_file = file?;
-        if regex.is_match(unwrapped_file.file_name().to_str().unwrap()) {
+pub async fn find_file<P: AsRef<Path>>(regex: &Regex, directory: P) -> Result<PathBuf> {
+    let directory = directory.as_ref();
+    for file in fs::read_dir(directory).map_err(Error::io(directory))? {
+        let unwrapped_file = file.map_err(Error::io(directory))?;
+        if regex.is_match(&unwrapped_file.file_name().to_string_lossy()) {
             return Ok(unwrapped_file.path())
         }
     }
-    Err(io::Error::new(
-        ErrorKind::Other,
-        "Cannot find the file",
-    ))
+    Err(Error::Io {
+        path: directory.to_path_buf(),
+        source: io::Error::new(ErrorKind::NotFound, format!("no file matching {regex}")),
+    })
 }
@@ -0,0 +1,210 @@
+//! Records which Spigot refs moved in a run.
+//!
+//! Every run writes the versions it built to `changelog.json`, and a summary to
+//! `changelog.md` whose first line is suitable as a commit subject.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use chrono::Utc;
+use proc_macros::serial_snake;
+
+use crate::error::{Error, Result};
+use crate::util::{timestamp, write_atomic};
+use crate::version::schema::spigot::SpigotVersionRefs;
+
+/// The machine-readable changelog, relative to the run directory.
+pub const CHANGELOG_FILE: &str = "changelog.json";
+
+/// The Markdown summary of the changelog, relative to the run directory.
+pub const CHANGELOG_SUMMARY_FILE: &str = "changelog.md";
+
+/// How many versions are named in the subject before they are only counted.
+const MAX_SUBJECT_VERSIONS: usize = 3;
+
+/// Why a version was built.
+#[serial_snake]
+#[derive(Copy, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    /// The version was not built before.
+    Added,
+    /// Some of its refs moved.
+    Updated,
+    /// Its refs did not move, but it was built again, such as with `--force`.
+    Rebuilt,
+}
+
+/// A ref that moved.
+#[serial_snake]
+pub struct RefChange {
+    /// The Spigot repository, such as `CraftBukkit`.
+    pub repository: String,
+    /// The previous commit, unset for added versions.
+    pub from: Option<String>,
+    pub to: String,
+}
+
+/// A version built in a run.
+#[serial_snake]
+pub struct VersionChange {
+    pub version: String,
+    pub kind: ChangeKind,
+    /// The refs that moved, in the order BuildData, Bukkit, CraftBukkit, Spigot.
+    pub refs: Vec<RefChange>,
+}
+
+/// The versions built in a run.
+#[serial_snake]
+pub struct Changelog {
+    /// When the changelog was written, in RFC 3339.
+    pub generated_at: String,
+    pub versions: Vec<VersionChange>,
+}
+
+/// The refs of a version with the names of their repositories.
+fn named_refs(refs: &SpigotVersionRefs) -> [(&'static str, &str); 4] {
+    [
+        ("BuildData", &refs.build_data),
+        ("Bukkit", &refs.bukkit),
+        ("CraftBukkit", &refs.craft_bukkit),
+        ("Spigot", &refs.spigot),
+    ]
+}
+
+/// The first seven characters of a commit hash.
+fn short(hash: &str) -> &str {
+    hash.get(..7).unwrap_or(hash)
+}
+
+impl VersionChange {
+    /// Compares the refs of a version before and after it was built.
+    ///
+    /// # Arguments
+    ///
+    /// * `version` - The version.
+    /// * `previous` - The refs of the previous build, if there was one.
+    /// * `current` - The refs it was built from now.
+    pub fn new(version: &str, previous: Option<&SpigotVersionRefs>, current: &SpigotVersionRefs) -> Self {
+        let refs: Vec<_> = match previous {
+            Some(previous) => named_refs(previous)
+                .into_iter()
+                .zip(named_refs(current))
+                .filter(|((_, from), (_, to))| from != to)
+                .map(|((repository, from), (_, to))| RefChange {
+                    repository: repository.to_owned(),
+                    from: Some(from.to_owned()),
+                    to: to.to_owned(),
+                })
+                .collect(),
+            None => named_refs(current)
+                .into_iter()
+                .map(|(repository, to)| RefChange {
+                    repository: repository.to_owned(),
+                    from: None,
+                    to: to.to_owned(),
+                })
+                .collect(),
+        };
+        let kind = match previous {
+            None => ChangeKind::Added,
+            Some(_) if refs.is_empty() => ChangeKind::Rebuilt,
+            Some(_) => ChangeKind::Updated,
+        };
+
+        Self {
+            version: version.to_owned(),
+            kind,
+            refs,
+        }
+    }
+}
+
+impl Changelog {
+    pub fn new(versions: Vec<VersionChange>) -> Self {
+        Self {
+            generated_at: timestamp(Utc::now()),
+            versions,
+        }
+    }
+
+    /// A one line summary, such as `Update 1.21.3, 1.21.4; add 1.21.5`.
+    pub fn subject(&self) -> String {
+        let groups = [
+            (ChangeKind::Updated, "update"),
+            (ChangeKind::Added, "add"),
+            (ChangeKind::Rebuilt, "rebuild"),
+        ];
+
+        let mut parts = vec![];
+        for (kind, verb) in groups {
+            let versions = self
+                .versions
+                .iter()
+                .filter(|change| change.kind == kind)
+                .map(|change| change.version.as_str())
+                .collect::<Vec<_>>();
+            match versions.len() {
+                0 => {}
+                1..=MAX_SUBJECT_VERSIONS => parts.push(format!("{verb} {}", versions.join(", "))),
+                count => parts.push(format!("{verb} {count} versions")),
+            }
+        }
+
+        let subject = parts.join("; ");
+        let mut chars = subject.chars();
+        match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars).collect(),
+            None => "No changes".to_owned(),
+        }
+    }
+
+    /// The changelog as Markdown: the [`Changelog::subject`], a blank line and
+    /// a list of the versions with the refs that moved.
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = format!("{}\n", self.subject());
+        if !self.versions.is_empty() {
+            markdown.push('\n');
+        }
+
+        for change in &self.versions {
+            match change.kind {
+                ChangeKind::Added => {
+                    let _ = writeln!(markdown, "- **{}**: new version", change.version);
+                }
+                ChangeKind::Rebuilt => {
+                    let _ = writeln!(markdown, "- **{}**: rebuilt, no ref changes", change.version);
+                }
+                ChangeKind::Updated => {
+                    let _ = writeln!(markdown, "- **{}**", change.version);
+                    for RefChange { repository, from, to } in &change.refs {
+                        let from = from.as_deref().map(short).unwrap_or("none");
+                        let _ = writeln!(markdown, "  - {repository}: `{from}` → `{}`", short(to));
+                    }
+                }
+            }
+        }
+
+        markdown
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(Error::io(path))?;
+
+        serde_json::from_str(&content).map_err(Error::metadata(path.display().to_string()))
+    }
+
+    /// Writes [`CHANGELOG_FILE`] and [`CHANGELOG_SUMMARY_FILE`] to a run
+    /// directory, replacing those of the previous run.
+    pub fn write<P: AsRef<Path>>(&self, run_dir: P) -> Result<()> {
+        let run_dir = run_dir.as_ref();
+        let path = run_dir.join(CHANGELOG_FILE);
+        let json = serde_json::to_string_pretty(self)
+            .map_err(Error::metadata(path.display().to_string()))?;
+
+        write_atomic(&path, json)?;
+        write_atomic(run_dir.join(CHANGELOG_SUMMARY_FILE), self.to_markdown())
+    }
+}
@@ -0,0 +1,99 @@
+//! SHA-256 and SHA-512 checksums of the output, for consumers that no longer
+//! accept SHA-1.
+//!
+//! Besides the checksums in the metadata, every run writes `SHA256SUMS` and
+//! `SHA512SUMS` manifests in the format of `sha256sum`, so a run directory can
+//! be checked with `sha256sum -c SHA256SUMS`.
+
+use std::path::{Path, PathBuf};
+
+use proc_macros::serial_snake;
+
+use crate::error::Result;
+use crate::index::{Index, INDEX_FILE};
+use crate::util::{sha256, sha512, write_atomic};
+
+/// The SHA-256 and SHA-512 hashes of a file.
+#[serial_snake]
+pub struct Checksums {
+    pub sha256: String,
+    pub sha512: String,
+}
+
+impl Checksums {
+    /// Hashes a file.
+    pub fn of<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+
+        Ok(Self {
+            sha256: sha256(path)?,
+            sha512: sha512(path)?,
+        })
+    }
+}
+
+/// The algorithms a checksum manifest is written for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 2] = [Algorithm::Sha256, Algorithm::Sha512];
+
+    /// The name of the manifest, relative to the run directory.
+    pub fn manifest_file(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "SHA256SUMS",
+            Algorithm::Sha512 => "SHA512SUMS",
+        }
+    }
+
+    /// Hashes a file with this algorithm.
+    pub fn hash<P: AsRef<Path>>(self, path: P) -> Result<String> {
+        match self {
+            Algorithm::Sha256 => sha256(path),
+            Algorithm::Sha512 => sha512(path),
+        }
+    }
+}
+
+/// Formats a checksum manifest, one `{hash}  {name}` line per file, in the
+/// order given.
+pub fn manifest(run_dir: &Path, algorithm: Algorithm, files: &[String]) -> Result<String> {
+    let mut manifest = String::new();
+    for name in files {
+        let hash = algorithm.hash(run_dir.join(name))?;
+        manifest.push_str(&format!("{hash}  {name}\n"));
+    }
+
+    Ok(manifest)
+}
+
+/// Writes a manifest for every [`Algorithm`], covering the index and every file
+/// it lists, including the history.
+///
+/// # Returns
+///
+/// The paths of the written manifests.
+pub fn write_manifests<P: AsRef<Path>>(run_dir: P, index: &Index) -> Result<Vec<PathBuf>> {
+    let run_dir = run_dir.as_ref();
+
+    let mut files = vec![INDEX_FILE.to_owned()];
+    for entry in &index.versions {
+        let history = entry.history.iter().flat_map(|build| build.files());
+        files.extend(entry.files().chain(history).map(|file| file.name.clone()));
+    }
+    files.sort();
+    files.dedup();
+
+    let mut manifests = vec![];
+    for algorithm in Algorithm::ALL {
+        let path = run_dir.join(algorithm.manifest_file());
+        write_atomic(&path, manifest(run_dir, algorithm, &files)?)?;
+        manifests.push(path);
+    }
+
+    Ok(manifests)
+}
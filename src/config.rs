@@ -1,8 +1,22 @@
-use std::{fs, io, path::{Path, PathBuf}};
+use std::{fs, path::{Path, PathBuf}, time::Duration};
 
 use proc_macros::serial_snake;
+use tracing::info;
 
+use crate::error::{Error, Result};
+use crate::checksums::Checksums;
+use crate::daemon;
+use crate::http::Integrity;
+use crate::maven;
+use crate::migrate;
+use crate::provenance::Provenance;
+use crate::publish::{self, PublishOptions};
+use crate::server::{self, ServeOptions};
+use crate::signing::Signer;
+use crate::sink::{SinkConfig, Sinks};
+use crate::maven::rules::{AppliedLibraryRule, LibraryRule};
 use crate::version::schema::spigot::SpigotVersionRefs;
+use crate::webhook::WebhookConfig;
 
 #[serial_snake]
 #[derive(Default)]
@@ -10,48 +24,236 @@ pub struct Config {
     java_8_home: String,
     java_16_home: String,
     java_17_home: String,
-    java_21_home: String
+    java_21_home: String,
+    /// Overrides the user agent sent with every HTTP request.
+    pub user_agent: Option<String>,
+    /// The expected SHA-1 hash of BuildTools. The download fails if it does not match.
+    pub buildtools_sha1: Option<String>,
+    /// The directory HTTP responses are cached in. Defaults to [`DEFAULT_CACHE_DIR`].
+    pub cache_dir: Option<String>,
+    /// A persistent directory BuildTools is run in. If unset, a temporary
+    /// directory is used, which is removed when the program exits.
+    pub workspace_dir: Option<String>,
+    /// Whether a vanilla jar that does not match Mojang's Piston metadata fails the
+    /// build instead of only logging a warning.
+    #[serde(default)]
+    pub strict_vanilla_check: bool,
+    /// The local Maven repository dependencies are resolved against. Defaults to
+    /// `~/.m2/repository`, which BuildTools populates.
+    pub maven_repository: Option<String>,
+    /// Rules replacing, excluding or adding libraries, applied in order.
+    #[serde(default)]
+    pub library_rules: Vec<LibraryRule>,
+    /// The private Ed25519 key the metadata and the index are signed with, a file
+    /// containing the hex encoded 32 byte seed. Nothing is signed if unset.
+    pub signing_key: Option<String>,
+    /// How many builds superseded by newer Spigot refs are kept per version in
+    /// the history directory. All are kept if unset, none if `0`.
+    pub history_retention: Option<usize>,
+    /// The git remote `publish` pushes to, a URL or the name of a remote of the
+    /// run directory. Nothing is pushed if unset.
+    pub publish_remote: Option<String>,
+    /// The branch `publish` commits to. Defaults to [`crate::publish::DEFAULT_BRANCH`].
+    pub publish_branch: Option<String>,
+    /// Where the output is uploaded to once it is verified, besides the run directory.
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+    /// How many seconds `daemon` waits between polls. Defaults to
+    /// [`crate::daemon::DEFAULT_INTERVAL`].
+    pub daemon_interval: Option<u64>,
+    /// The address `daemon` serves its health check on. Defaults to
+    /// [`crate::daemon::DEFAULT_HEALTH_ADDRESS`].
+    pub daemon_health_address: Option<String>,
+    /// The address `serve` listens on. Defaults to [`crate::server::DEFAULT_ADDRESS`].
+    pub serve_address: Option<String>,
+    /// Whether `serve` serves patched server jars.
+    #[serde(default)]
+    pub serve_patched_jars: bool,
+    /// Webhooks notified when versions are built or fail, and when a run ends.
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
 }
 
+/// The default directory HTTP responses are cached in, relative to the working directory.
+pub const DEFAULT_CACHE_DIR: &str = "cache";
+
 impl Config {
-    pub fn java_home(&self, version: u8) -> PathBuf {
-        PathBuf::from(match version {
+    pub fn buildtools_integrity(&self) -> Integrity {
+        self.buildtools_sha1
+            .as_deref()
+            .map(Integrity::sha1)
+            .unwrap_or_default()
+    }
+
+    pub fn cache_dir(&self) -> PathBuf {
+        PathBuf::from(self.cache_dir.as_deref().unwrap_or(DEFAULT_CACHE_DIR))
+    }
+
+    pub fn workspace_dir(&self) -> Option<PathBuf> {
+        self.workspace_dir.as_deref().map(PathBuf::from)
+    }
+
+    pub fn maven_repository(&self) -> Option<PathBuf> {
+        self.maven_repository
+            .as_deref()
+            .map(PathBuf::from)
+            .or_else(maven::local_repository)
+    }
+
+    /// The publishing options, verifying the output with the public key of the
+    /// configured signing key, if any.
+    pub fn publish_options(&self) -> Result<PublishOptions> {
+        Ok(PublishOptions {
+            remote: self.publish_remote.clone(),
+            branch: self
+                .publish_branch
+                .clone()
+                .unwrap_or_else(|| publish::DEFAULT_BRANCH.to_owned()),
+            public_key: self.signer()?.map(|signer| signer.public_key()),
+        })
+    }
+
+    pub fn daemon_interval(&self) -> Duration {
+        self.daemon_interval
+            .map(Duration::from_secs)
+            .unwrap_or(daemon::DEFAULT_INTERVAL)
+    }
+
+    pub fn daemon_health_address(&self) -> &str {
+        self.daemon_health_address
+            .as_deref()
+            .unwrap_or(daemon::DEFAULT_HEALTH_ADDRESS)
+    }
+
+    pub fn serve_address(&self) -> &str {
+        self.serve_address.as_deref().unwrap_or(server::DEFAULT_ADDRESS)
+    }
+
+    /// The options of `serve`, verifying the metadata with the public key of the
+    /// configured signing key, if any, before patching.
+    pub fn serve_options(&self) -> Result<ServeOptions> {
+        Ok(ServeOptions {
+            patched_jars: self.serve_patched_jars,
+            cache_dir: self.cache_dir(),
+            public_key: self.signer()?.map(|signer| signer.public_key()),
+        })
+    }
+
+    /// Creates the configured sinks.
+    pub fn sinks(&self, run_dir: &Path) -> Result<Sinks> {
+        let public_key = self.signer()?.map(|signer| signer.public_key());
+        self.sinks
+            .iter()
+            .map(|sink| sink.build(run_dir, public_key))
+            .collect::<Result<_>>()
+            .map(Sinks::new)
+    }
+
+    /// Reads the configured signing key.
+    pub fn signer(&self) -> Result<Option<Signer>> {
+        self.signing_key.as_deref().map(Signer::read).transpose()
+    }
+
+    /// Returns the configured `JAVA_HOME` for a Java version.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Config`] if there is no setting for the Java version or it is empty.
+    pub fn java_home(&self, version: u8) -> Result<PathBuf> {
+        let home = match version {
             8 =>  &*self.java_8_home,
             16 => &*self.java_16_home,
             17 => &*self.java_17_home,
             21 => &*self.java_21_home,
-            _ => panic!("invalid java home version: {version}")
-        })
+            _ => return Err(Error::config(CONFIG_FILE, format!("no java home setting for java {version}")))
+        };
+
+        if home.is_empty() {
+            return Err(Error::config(CONFIG_FILE, format!("java_{version}_home is not set")));
+        }
+
+        Ok(PathBuf::from(home))
     }
 }
 
-pub fn read_config<P: AsRef<Path>>(path: P) -> io::Result<Config> {
-    let content = fs::read_to_string(path)?;
-    toml::from_str::<Config>(&content).map_err(
-        |e| io::Error::new(io::ErrorKind::Other, e.to_string())
-    )
+/// The name of the configuration file, relative to the working directory.
+pub const CONFIG_FILE: &str = "config.toml";
+
+pub fn read_config<P: AsRef<Path>>(path: P) -> Result<Config> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path).map_err(Error::io(path))?;
+    toml::from_str::<Config>(&content).map_err(|e| Error::config(path, e))
 }
 
+/// Reads the config, writing the default config to `path` first if there is none.
+pub fn read_or_create_config<P: AsRef<Path>>(path: P) -> Result<Config> {
+    let path = path.as_ref();
+    if !path.exists() {
+        let default_config = toml::to_string_pretty(&Config::default()).map_err(|e| Error::config(path, e))?;
+        fs::write(path, default_config).map_err(Error::io(path))?;
+        info!("Generated default config file.");
+    }
+
+    read_config(path)
+}
+
+/// The version of the [`PatchedVersionMeta`] format. It is increased whenever
+/// the format changes, and older metadata is upgraded by [`crate::migrate`].
+pub const METADATA_SCHEMA_VERSION: u32 = 4;
+
 #[serial_snake]
 pub struct PatchedVersionMeta {
+    #[serde(default = "legacy_schema_version")]
+    pub schema_version: u32,
     pub patch_file: String,
     pub commit_hashes: SpigotVersionRefs,
     pub patch_hash: String,
     pub vanilla_jar_hash: String,
     pub patched_jar_hash: String,
-    pub vanilla_download_url: String
+    pub vanilla_download_url: String,
+    /// The SHA-1 hash of the server jar at `vanilla_download_url`, according to Piston.
+    #[serde(default)]
+    pub vanilla_download_hash: Option<String>,
+    /// The size of the server jar at `vanilla_download_url`, according to Piston.
+    #[serde(default)]
+    pub vanilla_download_size: Option<u64>,
+    /// The library rules from the config that changed the libraries of this version.
+    #[serde(default)]
+    pub library_rules: Vec<AppliedLibraryRule>,
+    /// How the version was built.
+    #[serde(default)]
+    pub provenance: Option<Provenance>,
+    /// The SHA-256 and SHA-512 hashes of the patch, next to the SHA-1 `patch_hash`.
+    #[serde(default)]
+    pub patch_checksums: Option<Checksums>,
+    /// The SHA-256 and SHA-512 hashes of the vanilla jar.
+    #[serde(default)]
+    pub vanilla_jar_checksums: Option<Checksums>,
+    /// The SHA-256 and SHA-512 hashes of the patched jar.
+    #[serde(default)]
+    pub patched_jar_checksums: Option<Checksums>,
+}
+
+fn legacy_schema_version() -> u32 {
+    migrate::LEGACY_SCHEMA_VERSION
 }
 
 impl PatchedVersionMeta {
-    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        let content = fs::read_to_string(path)?;
+    /// Reads metadata, upgrading it to the current schema in memory if it is older.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(Error::io(path))?;
+        let mut meta = serde_json::from_str::<serde_json::Value>(&content)
+            .map_err(Error::metadata(path.display().to_string()))?;
+        migrate::migrate_metadata(&mut meta);
 
-        serde_json::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+        serde_json::from_value(meta).map_err(Error::metadata(path.display().to_string()))
     }
 
-    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
-        let json = serde_json::to_string_pretty(self)?;
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let json = serde_json::to_string_pretty(self).map_err(Error::metadata(path.display().to_string()))?;
 
-        fs::write(path, json)
+        fs::write(path, json).map_err(Error::io(path))
     }
 }
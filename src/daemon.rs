@@ -0,0 +1,410 @@
+//! Keeps the output up to date as a long-running service.
+//!
+//! The daemon polls Spigot periodically, queues every version whose output is
+//! missing or was built from other refs than Spigot lists now, and builds the
+//! queue one version at a time in a single [`crate::Run`]. Its state is persisted
+//! after every step and served as a health check.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{DateTime, TimeDelta, Utc};
+use proc_macros::serial_snake;
+use tokio::net::TcpListener;
+use tracing::{error, info, warn};
+
+use crate::error::{Error, Result};
+use crate::util::{timestamp, write_atomic};
+use crate::version::{fetch_spigot_version_meta, fetch_versions};
+
+/// The name of the state file, in the cache directory.
+pub const STATE_FILE: &str = "daemon.json";
+
+/// How long the daemon waits between polls if no interval is configured.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The address the health check is served on if none is configured.
+pub const DEFAULT_HEALTH_ADDRESS: &str = "127.0.0.1:8080";
+
+/// How long past its next poll the daemon is still considered healthy.
+const HEALTH_GRACE: Duration = Duration::from_secs(5 * 60);
+
+/// How long a build may take before it is cancelled and the version fails.
+pub const BUILD_TIMEOUT: Duration = Duration::from_secs(2 * 60 * 60);
+
+/// The longest a version that keeps failing waits to be built again.
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// The persisted state of the daemon.
+#[serial_snake]
+#[derive(Default)]
+pub struct DaemonState {
+    pub started_at: Option<String>,
+    pub last_poll: Option<Poll>,
+    /// The versions waiting to be built, in order.
+    #[serde(default)]
+    pub queue: Vec<String>,
+    /// The version being built.
+    pub building: Option<String>,
+    /// Why the last run could not start, if it did not.
+    pub run_error: Option<String>,
+    #[serde(default)]
+    pub versions: BTreeMap<String, VersionState>,
+}
+
+/// The outcome of polling Spigot.
+#[serial_snake]
+pub struct Poll {
+    pub started_at: String,
+    pub finished_at: String,
+    /// Why the poll failed, if it did.
+    pub error: Option<String>,
+}
+
+/// The builds of a version.
+#[serial_snake]
+#[derive(Default)]
+pub struct VersionState {
+    pub last_built: Option<String>,
+    pub last_failed: Option<String>,
+    /// How often the version failed to build since it last succeeded.
+    #[serde(default)]
+    pub failures: u32,
+    /// Why the version last failed to build, unset once it succeeds.
+    pub error: Option<String>,
+}
+
+/// What the daemon is doing.
+#[serial_snake]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Nothing was polled yet.
+    Starting,
+    Building,
+    /// Waiting for the next poll.
+    Idle,
+}
+
+/// The health check, served as JSON.
+#[serial_snake]
+pub struct Health {
+    pub healthy: bool,
+    pub status: Status,
+    pub state: DaemonState,
+}
+
+impl VersionState {
+    /// When the version may be built again after failing: one poll interval
+    /// after its last failure, doubled for every further failure, up to
+    /// [`MAX_RETRY_DELAY`].
+    ///
+    /// # Returns
+    ///
+    /// The time, or `None` if the version did not fail since it last succeeded.
+    pub fn retry_at(&self, interval: Duration) -> Option<DateTime<Utc>> {
+        if self.failures == 0 {
+            return None;
+        }
+        let last_failed = DateTime::parse_from_rfc3339(self.last_failed.as_deref()?).ok()?;
+        let delay = interval
+            .saturating_mul(1 << (self.failures - 1).min(16))
+            .min(MAX_RETRY_DELAY);
+
+        Some(last_failed.with_timezone(&Utc) + TimeDelta::from_std(delay).ok()?)
+    }
+}
+
+impl DaemonState {
+    /// Reads the state file. A missing or unreadable state file yields an
+    /// empty state, since all of it can be recovered by polling again.
+    pub fn read<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Self::default();
+        }
+
+        match fs::read_to_string(path)
+            .map_err(Error::io(path))
+            .and_then(|json| serde_json::from_str(&json).map_err(Error::metadata("daemon state")))
+        {
+            Ok(state) => state,
+            Err(err) => {
+                warn!("Discarding daemon state ({err})");
+                Self::default()
+            }
+        }
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(Error::io(parent))?;
+        }
+
+        let json = serde_json::to_string_pretty(self).map_err(Error::metadata("daemon state"))?;
+        write_atomic(path, json)
+    }
+
+    fn record_failure(&mut self, version: &str, error: String) {
+        let state = self.versions.entry(version.to_owned()).or_default();
+        state.last_failed = Some(timestamp(Utc::now()));
+        state.failures += 1;
+        state.error = Some(error);
+    }
+}
+
+struct Shared {
+    state: DaemonState,
+    /// When the daemon last did anything, to detect it hanging.
+    heartbeat: Instant,
+}
+
+/// A daemon building a run directory.
+#[derive(Clone)]
+pub struct Daemon {
+    run_dir: PathBuf,
+    state_file: PathBuf,
+    interval: Duration,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Daemon {
+    /// Creates a daemon, resuming the state in `state_file`. A build that was
+    /// interrupted counts as failed.
+    ///
+    /// # Arguments
+    ///
+    /// * `run_dir` - The run directory.
+    /// * `state_file` - The file the state is persisted in.
+    /// * `interval` - How long to wait between polls.
+    pub fn new<P: Into<PathBuf>, S: Into<PathBuf>>(run_dir: P, state_file: S, interval: Duration) -> Result<Self> {
+        let state_file = state_file.into();
+        let mut state = DaemonState::read(&state_file);
+        state.started_at = Some(timestamp(Utc::now()));
+        if let Some(version) = state.building.take() {
+            warn!("Building {version} was interrupted");
+            state.record_failure(&version, "interrupted".to_owned());
+        }
+        state.write(&state_file)?;
+
+        Ok(Self {
+            run_dir: run_dir.into(),
+            state_file,
+            interval,
+            shared: Arc::new(Mutex::new(Shared {
+                state,
+                heartbeat: Instant::now(),
+            })),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Shared> {
+        self.shared.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Changes the state and persists it.
+    fn update<F: FnOnce(&mut DaemonState)>(&self, change: F) -> Result<()> {
+        let mut shared = self.lock();
+        change(&mut shared.state);
+        shared.heartbeat = Instant::now();
+        shared.state.write(&self.state_file)
+    }
+
+    pub fn state(&self) -> DaemonState {
+        self.lock().state.clone()
+    }
+
+    /// The daemon is healthy while a build is not overdue to be cancelled after
+    /// [`BUILD_TIMEOUT`], and otherwise if its last poll succeeded, the last
+    /// run could start and the next poll is not overdue.
+    pub fn health(&self) -> Health {
+        let shared = self.lock();
+        let state = shared.state.clone();
+        let status = match (&state.building, &state.last_poll) {
+            (Some(_), _) => Status::Building,
+            (None, None) => Status::Starting,
+            (None, Some(_)) => Status::Idle,
+        };
+        let healthy = match status {
+            Status::Building => shared.heartbeat.elapsed() <= BUILD_TIMEOUT + HEALTH_GRACE,
+            Status::Starting | Status::Idle => {
+                state.last_poll.as_ref().is_none_or(|poll| poll.error.is_none())
+                    && state.run_error.is_none()
+                    && shared.heartbeat.elapsed() <= self.interval + HEALTH_GRACE
+            }
+        };
+
+        Health {
+            healthy,
+            status,
+            state,
+        }
+    }
+
+    /// Polls Spigot and queues every version that is not up to date. Versions
+    /// whose metadata cannot be fetched are skipped, and so are versions that
+    /// failed until [`VersionState::retry_at`].
+    ///
+    /// # Returns
+    ///
+    /// The queued versions.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the versions cannot be fetched.
+    pub async fn poll(&self) -> Result<Vec<String>> {
+        let started_at = timestamp(Utc::now());
+        let result = self.outdated_versions().await;
+        let mut queued = vec![];
+
+        self.update(|state| {
+            let now = Utc::now();
+            for version in result.iter().flatten() {
+                if state.building.as_ref() == Some(version) || state.queue.contains(version) {
+                    continue;
+                }
+                let retry_at = state
+                    .versions
+                    .get(version)
+                    .and_then(|version| version.retry_at(self.interval));
+                if let Some(retry_at) = retry_at.filter(|retry_at| *retry_at > now) {
+                    info!("Not retrying {version} before {}", timestamp(retry_at));
+                    continue;
+                }
+                state.queue.push(version.clone());
+                queued.push(version.clone());
+            }
+            state.last_poll = Some(Poll {
+                started_at,
+                finished_at: timestamp(Utc::now()),
+                error: result.as_ref().err().map(ToString::to_string),
+            });
+        })?;
+
+        result.map(|_| queued)
+    }
+
+    async fn outdated_versions(&self) -> Result<Vec<String>> {
+        let mut outdated = vec![];
+        for version in fetch_versions().await? {
+            match fetch_spigot_version_meta(version.clone()).await {
+                Ok(remote_meta) if crate::is_up_to_date(&self.run_dir, &version, &remote_meta) => {}
+                Ok(_) => outdated.push(version),
+                Err(err) => warn!("Not checking {version}: {err}"),
+            }
+        }
+
+        Ok(outdated)
+    }
+
+    /// Builds the queued versions one at a time in a single run, until the
+    /// queue is empty. A version that fails is recorded and polled again later.
+    pub async fn build_queue(&self) -> Result<()> {
+        if self.state().queue.is_empty() {
+            return Ok(());
+        }
+
+        // A run that cannot start fails no version; the queue is kept for the
+        // next attempt.
+        let run = crate::Run::start(self.run_dir.clone(), false).await;
+        self.update(|state| state.run_error = run.as_ref().err().map(ToString::to_string))?;
+        let mut run = match run {
+            Ok(run) => run,
+            Err(err) => {
+                error!("Failed starting a run: {err}");
+                return Ok(());
+            }
+        };
+
+        loop {
+            let mut next = None;
+            self.update(|state| {
+                if !state.queue.is_empty() {
+                    next = Some(state.queue.remove(0));
+                }
+                state.building = next.clone();
+            })?;
+            let Some(version) = next else {
+                break;
+            };
+
+            info!("Building queued version {version}");
+            let result = run.build_within(&version, BUILD_TIMEOUT).await;
+
+            self.update(|state| {
+                state.building = None;
+                match result {
+                    Ok(()) => {
+                        let state = state.versions.entry(version.clone()).or_default();
+                        state.last_built = Some(timestamp(Utc::now()));
+                        state.failures = 0;
+                        state.error = None;
+                    }
+                    Err(err) => {
+                        error!("Failed building {version}: {err}");
+                        state.record_failure(&version, err.to_string());
+                    }
+                }
+            })?;
+        }
+
+        if let Err(err) = run.finish(Ok(())).await {
+            error!("Failed finishing the run: {err}");
+        }
+        Ok(())
+    }
+
+    /// Polls and builds, forever or only once.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the state cannot be persisted. Failed polls and builds are
+    /// only recorded.
+    pub async fn run(&self, once: bool) -> Result<()> {
+        loop {
+            match self.poll().await {
+                Ok(queued) => info!("Queued {} versions: {queued:?}", queued.len()),
+                Err(err) => error!("Failed polling Spigot: {err}"),
+            }
+            self.build_queue().await?;
+
+            if once {
+                return Ok(());
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+
+    /// Serves the health check on `/health`, with status 200 if the daemon
+    /// is healthy and 503 otherwise.
+    pub async fn serve_health(self, listener: TcpListener) -> Result<()> {
+        let address = listener
+            .local_addr()
+            .map(|address| address.to_string())
+            .unwrap_or_default();
+        let app = Router::new()
+            .route("/health", get(health))
+            .with_state(self);
+
+        info!("Serving the health check on {address}");
+        axum::serve(listener, app).await.map_err(Error::io(address))
+    }
+}
+
+async fn health(State(daemon): State<Daemon>) -> (StatusCode, Json<Health>) {
+    let health = daemon.health();
+    let status = if health.healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(health))
+}
@@ -0,0 +1,185 @@
+//! The error type shared by the whole crate.
+
+use crate::http::HttpError;
+use std::fmt::{self, Display};
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// An error that occurred while generating or applying patches.
+#[derive(Debug)]
+pub enum Error {
+    /// An HTTP request failed.
+    Network(HttpError),
+    /// A file or directory could not be accessed.
+    Io { path: PathBuf, source: io::Error },
+    /// Remote or local metadata could not be parsed.
+    Metadata {
+        what: String,
+        source: serde_json::Error,
+    },
+    /// BuildTools could not be run or did not produce a jar.
+    BuildTools { version: String, message: String },
+    /// A jar could not be read or extracted.
+    Jar { path: PathBuf, message: String },
+    /// A POM could not be read or its dependencies could not be resolved.
+    Maven { path: PathBuf, message: String },
+    /// The configuration is missing, malformed or incomplete.
+    Config { path: PathBuf, message: String },
+    /// A patch could not be generated or applied.
+    Patch { path: PathBuf, message: String },
+    /// A Minecraft version could not be parsed.
+    InvalidVersion(String),
+    /// A signature is missing or invalid, or a signed hash does not match.
+    Signature { path: PathBuf, message: String },
+    /// A file does not match the hash the index lists for it.
+    Verification { path: PathBuf, message: String },
+    /// A git operation on the run directory failed.
+    Git { path: PathBuf, source: git2::Error },
+    /// Building a version failed with a Maven, jar or metadata error.
+    Version { version: String, source: Box<Error> },
+    /// The vanilla jar BuildTools used does not match Mojang's metadata.
+    VanillaMismatch {
+        version: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl Error {
+    /// Returns a closure wrapping an [`io::Error`] with the path it occurred at,
+    /// for use with [`Result::map_err`].
+    pub fn io<P: AsRef<Path>>(path: P) -> impl FnOnce(io::Error) -> Error {
+        let path = path.as_ref().to_path_buf();
+        move |source| Error::Io { path, source }
+    }
+
+    pub fn build_tools<V: Into<String>, M: Display>(version: V, message: M) -> Self {
+        Error::BuildTools {
+            version: version.into(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn jar<P: AsRef<Path>, M: Display>(path: P, message: M) -> Self {
+        Error::Jar {
+            path: path.as_ref().to_path_buf(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn maven<P: AsRef<Path>, M: Display>(path: P, message: M) -> Self {
+        Error::Maven {
+            path: path.as_ref().to_path_buf(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn config<P: AsRef<Path>, M: Display>(path: P, message: M) -> Self {
+        Error::Config {
+            path: path.as_ref().to_path_buf(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn patch<P: AsRef<Path>, M: Display>(path: P, message: M) -> Self {
+        Error::Patch {
+            path: path.as_ref().to_path_buf(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn signature<P: AsRef<Path>, M: Display>(path: P, message: M) -> Self {
+        Error::Signature {
+            path: path.as_ref().to_path_buf(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn verification<P: AsRef<Path>, M: Display>(path: P, message: M) -> Self {
+        Error::Verification {
+            path: path.as_ref().to_path_buf(),
+            message: message.to_string(),
+        }
+    }
+
+    /// Returns a closure wrapping a [`git2::Error`] with the repository it
+    /// occurred in, for use with [`Result::map_err`].
+    pub fn git<P: AsRef<Path>>(path: P) -> impl FnOnce(git2::Error) -> Error {
+        let path = path.as_ref().to_path_buf();
+        move |source| Error::Git { path, source }
+    }
+
+    /// Returns a closure adding the version being built to Maven, jar and
+    /// metadata errors, which do not name it otherwise, for use with
+    /// [`Result::map_err`]. Other errors are returned as is.
+    pub fn in_version<V: Into<String>>(version: V) -> impl FnOnce(Error) -> Error {
+        let version = version.into();
+        move |err| match err {
+            Error::Maven { .. } | Error::Jar { .. } | Error::Metadata { .. } => Error::Version {
+                version,
+                source: Box::new(err),
+            },
+            err => err,
+        }
+    }
+
+    /// Returns a closure wrapping a [`serde_json::Error`] with a description of
+    /// what was being parsed, for use with [`Result::map_err`].
+    pub fn metadata<W: Into<String>>(what: W) -> impl FnOnce(serde_json::Error) -> Error {
+        let what = what.into();
+        move |source| Error::Metadata { what, source }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Network(err) => write!(f, "{err}"),
+            Error::Io { path, source } => write!(f, "{}: {source}", path.display()),
+            Error::Metadata { what, source } => write!(f, "invalid {what}: {source}"),
+            Error::BuildTools { version, message } => {
+                write!(f, "BuildTools failed for {version}: {message}")
+            }
+            Error::Jar { path, message } => write!(f, "jar {}: {message}", path.display()),
+            Error::Maven { path, message } => write!(f, "pom {}: {message}", path.display()),
+            Error::Config { path, message } => write!(f, "config {}: {message}", path.display()),
+            Error::Patch { path, message } => write!(f, "patch {}: {message}", path.display()),
+            Error::Signature { path, message } => {
+                write!(f, "signature of {}: {message}", path.display())
+            }
+            Error::Verification { path, message } => write!(f, "{} {message}", path.display()),
+            Error::Git { path, source } => write!(f, "git {}: {source}", path.display()),
+            Error::InvalidVersion(version) => write!(f, "invalid minecraft version: {version}"),
+            Error::Version { version, source } => write!(f, "{version}: {source}"),
+            Error::VanillaMismatch {
+                version,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "vanilla jar of {version} has sha1 {actual}, but Piston lists {expected}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Network(err) => Some(err),
+            Error::Io { source, .. } => Some(source),
+            Error::Metadata { source, .. } => Some(source),
+            Error::Git { source, .. } => Some(source),
+            Error::Version { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<HttpError> for Error {
+    fn from(err: HttpError) -> Self {
+        Error::Network(err)
+    }
+}
@@ -0,0 +1,212 @@
+//! Keeps the builds of a version that were superseded by newer Spigot refs.
+//!
+//! Before a version is rebuilt for new refs, its previous patch, metadata and
+//! library files are copied to `history/{version}/{patched_jar_hash}/`, so
+//! servers pinned to an older Spigot build can still get their patch.
+
+use std::cmp::Reverse;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use tracing::{info, warn};
+
+use crate::config::PatchedVersionMeta;
+use crate::error::{Error, Result};
+use crate::signing::signature_path;
+
+/// The directory superseded builds are kept in, relative to the run directory.
+pub const HISTORY_DIR: &str = "history";
+
+/// A superseded build in the history directory.
+#[derive(Debug)]
+pub struct ArchivedBuild {
+    /// The directory of the build, relative to the run directory.
+    pub dir: String,
+    pub meta: PatchedVersionMeta,
+    /// When the build finished, or when its metadata was written if its
+    /// provenance is unknown.
+    pub built_at: DateTime<Utc>,
+}
+
+impl ArchivedBuild {
+    /// The path of a file of the build, relative to the run directory.
+    pub fn file(&self, name: &str) -> String {
+        format!("{}/{name}", self.dir)
+    }
+}
+
+/// Copies a file, keeping its modification time, which orders builds without
+/// provenance.
+fn copy_file(from: &Path, to: &Path) -> io::Result<()> {
+    fs::copy(from, to)?;
+    let modified = fs::metadata(from)?.modified()?;
+    File::options().write(true).open(to)?.set_modified(modified)
+}
+
+/// Archives the current build of a version before it is overwritten.
+///
+/// Nothing is archived if the version has no valid metadata or patch, or if a
+/// build with the same patched jar hash already is in the history.
+///
+/// # Returns
+///
+/// The directory of the archived build, if the version had one.
+pub fn archive<P: AsRef<Path>>(run_dir: P, version: &str) -> Result<Option<PathBuf>> {
+    let run_dir = run_dir.as_ref();
+    let meta = match PatchedVersionMeta::read(run_dir.join(format!("{version}.json"))) {
+        Ok(meta) => meta,
+        Err(err) => {
+            warn!("Not archiving the previous build of {version}: {err}");
+            return Ok(None);
+        }
+    };
+    if !run_dir.join(&meta.patch_file).is_file() {
+        warn!("Not archiving the previous build of {version}: {} is missing", meta.patch_file);
+        return Ok(None);
+    }
+
+    let version_dir = run_dir.join(HISTORY_DIR).join(version);
+    let dir = version_dir.join(&meta.patched_jar_hash);
+    if dir.exists() {
+        info!("Previous build of {version} is already archived");
+        return Ok(Some(dir));
+    }
+
+    // Copy into a temporary directory first, so a failed copy never leaves a
+    // partial build in the history.
+    let temp = version_dir.join(format!("{}.tmp", meta.patched_jar_hash));
+    let _ = fs::remove_dir_all(&temp);
+    fs::create_dir_all(&temp).map_err(Error::io(&temp))?;
+
+    let metadata = run_dir.join(format!("{version}.json"));
+    let files = [
+        signature_path(&metadata),
+        metadata,
+        run_dir.join(&meta.patch_file),
+        run_dir.join(format!("{version}.libs")),
+        run_dir.join(format!("{version}.libraries.json")),
+    ];
+    for file in files.iter().filter(|file| file.is_file()) {
+        let target = temp.join(file.file_name().unwrap_or_default());
+        copy_file(file, &target).map_err(Error::io(&target))?;
+    }
+    fs::rename(&temp, &dir).map_err(Error::io(&dir))?;
+
+    info!("Archived the previous build of {version}");
+    Ok(Some(dir))
+}
+
+/// Lists the archived builds of a version, newest first.
+pub fn builds<P: AsRef<Path>>(run_dir: P, version: &str) -> Result<Vec<ArchivedBuild>> {
+    let run_dir = run_dir.as_ref();
+    let version_dir = run_dir.join(HISTORY_DIR).join(version);
+    if !version_dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut builds = vec![];
+    for entry in fs::read_dir(&version_dir).map_err(Error::io(&version_dir))? {
+        let path = entry.map_err(Error::io(&version_dir))?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if !path.is_dir() || name.ends_with(".tmp") {
+            continue;
+        }
+
+        let metadata = path.join(format!("{version}.json"));
+        let meta = match PatchedVersionMeta::read(&metadata) {
+            Ok(meta) => meta,
+            Err(err) => {
+                warn!("Ignoring archived build {}: {err}", path.display());
+                continue;
+            }
+        };
+        let finished_at = meta
+            .provenance
+            .as_ref()
+            .and_then(|provenance| DateTime::parse_from_rfc3339(&provenance.finished_at).ok());
+        let built_at = match finished_at {
+            Some(finished_at) => finished_at.with_timezone(&Utc),
+            None => fs::metadata(&metadata)
+                .and_then(|metadata| metadata.modified())
+                .map_err(Error::io(&metadata))?
+                .into(),
+        };
+
+        builds.push(ArchivedBuild {
+            dir: format!("{HISTORY_DIR}/{version}/{name}"),
+            meta,
+            built_at,
+        });
+    }
+
+    builds.sort_by_key(|build| Reverse(build.built_at));
+    Ok(builds)
+}
+
+/// Removes all but the newest `keep` archived builds of every version, and
+/// every archived build whose metadata is unreadable or invalid.
+///
+/// # Returns
+///
+/// The removed files, relative to the run directory with `/` as separator, so
+/// they can be removed from the sinks too.
+pub fn prune<P: AsRef<Path>>(run_dir: P, keep: usize) -> Result<Vec<String>> {
+    let run_dir = run_dir.as_ref();
+    let history_dir = run_dir.join(HISTORY_DIR);
+    if !history_dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut removed = vec![];
+    for entry in fs::read_dir(&history_dir).map_err(Error::io(&history_dir))? {
+        let path = entry.map_err(Error::io(&history_dir))?.path();
+        let Some(version) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if !path.is_dir() {
+            continue;
+        }
+
+        let builds = builds(run_dir, version)?;
+        // `builds` skips these, so they would never count against the retention.
+        for entry in fs::read_dir(&path).map_err(Error::io(&path))? {
+            let build_dir = entry.map_err(Error::io(&path))?.path();
+            let Some(name) = build_dir.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let dir = format!("{HISTORY_DIR}/{version}/{name}");
+            if build_dir.is_dir() && !name.ends_with(".tmp") && !builds.iter().any(|build| build.dir == dir) {
+                removed.extend(remove_build(run_dir, &dir)?);
+                warn!("Removed archived build {dir}, its metadata is unreadable or invalid");
+            }
+        }
+
+        for build in builds.into_iter().skip(keep) {
+            removed.extend(remove_build(run_dir, &build.dir)?);
+            info!("Removed archived build {}", build.dir);
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Removes the directory of an archived build.
+///
+/// # Returns
+///
+/// The removed files, relative to the run directory.
+fn remove_build(run_dir: &Path, build_dir: &str) -> Result<Vec<String>> {
+    let dir = run_dir.join(build_dir);
+    let mut removed = vec![];
+    for file in fs::read_dir(&dir).map_err(Error::io(&dir))? {
+        let name = file.map_err(Error::io(&dir))?.file_name();
+        removed.push(format!("{build_dir}/{}", name.to_string_lossy()));
+    }
+    fs::remove_dir_all(&dir).map_err(Error::io(&dir))?;
+
+    Ok(removed)
+}
@@ -0,0 +1,121 @@
+//! An on-disk cache for HTTP responses, revalidated with ETag and Last-Modified.
+
+use proc_macros::serial_snake;
+use reqwest::header::{HeaderMap, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{RequestBuilder, Url};
+use sha1::{Digest, Sha1};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The validators a server sent along with a cached response.
+#[serial_snake]
+#[derive(Default)]
+pub struct CacheEntry {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl CacheEntry {
+    /// Reads the validators from the headers of a response.
+    pub fn from_headers(url: &Url, headers: &HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned)
+        };
+
+        Self {
+            url: url.to_string(),
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+
+    /// The validator for an `If-Range` header: the ETag if it is strong,
+    /// otherwise Last-Modified.
+    pub fn if_range(&self) -> Option<&str> {
+        self.etag
+            .as_deref()
+            .filter(|etag| !etag.starts_with("W/"))
+            .or(self.last_modified.as_deref())
+    }
+
+    /// Makes a request conditional on the cached response being outdated.
+    pub fn apply(&self, mut request: RequestBuilder) -> RequestBuilder {
+        if let Some(etag) = &self.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        request
+    }
+}
+
+/// A directory holding cached response bodies and their validators.
+///
+/// Every URL is stored as two files named after the SHA-1 hash of the URL:
+/// `{hash}.body` with the raw body and `{hash}.json` with its [`CacheEntry`].
+#[derive(Clone, Debug)]
+pub struct HttpCache {
+    dir: PathBuf,
+}
+
+impl HttpCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn key(url: &Url) -> String {
+        hex::encode(Sha1::digest(url.as_str().as_bytes()))
+    }
+
+    /// The path of the cached body of `url`.
+    pub fn body_path(&self, url: &Url) -> PathBuf {
+        self.dir.join(format!("{}.body", Self::key(url)))
+    }
+
+    fn entry_path(&self, url: &Url) -> PathBuf {
+        self.dir.join(format!("{}.json", Self::key(url)))
+    }
+
+    /// Looks up the cached validators of `url`, if its body is cached too.
+    pub fn lookup(&self, url: &Url) -> Option<CacheEntry> {
+        if !self.body_path(url).exists() {
+            return None;
+        }
+
+        let content = fs::read_to_string(self.entry_path(url)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    /// Reads the cached body of `url` as text.
+    pub fn read(&self, url: &Url) -> io::Result<String> {
+        fs::read_to_string(self.body_path(url))
+    }
+
+    /// Stores a text body for `url`.
+    pub fn store(&self, entry: &CacheEntry, url: &Url, body: &str) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.body_path(url), body)?;
+        self.write_entry(entry, url)
+    }
+
+    /// Stores a copy of a downloaded file for `url`.
+    pub fn store_file(&self, entry: &CacheEntry, url: &Url, file: &Path) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::copy(file, self.body_path(url))?;
+        self.write_entry(entry, url)
+    }
+
+    fn write_entry(&self, entry: &CacheEntry, url: &Url) -> io::Result<()> {
+        fs::write(self.entry_path(url), serde_json::to_string_pretty(entry)?)
+    }
+}
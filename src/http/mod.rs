@@ -0,0 +1,640 @@
+//! The shared HTTP client used for every request the generator makes.
+
+pub mod cache;
+
+use cache::{CacheEntry, HttpCache};
+use futures_util::StreamExt;
+use reqwest::{Client, IntoUrl, RequestBuilder, Response, StatusCode, Url};
+use std::fmt::{self, Display};
+use crate::error;
+use crate::util::hash_file;
+use sha1::Sha1;
+use reqwest::header::{CONTENT_RANGE, IF_RANGE, RANGE};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// The user agent used for all HTTP requests, unless overridden with [`set_user_agent`].
+pub const DEFAULT_USER_AGENT: &str = concat!(
+    "bin-patch-gen/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/SploonMC/bin-patch-gen)"
+);
+
+/// How long establishing a connection may take.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long a single read from a connection may take.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many times a request is attempted before giving up.
+pub const MAX_ATTEMPTS: u32 = 5;
+
+/// The delay before the first retry. It doubles with every further attempt.
+pub const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+static USER_AGENT: OnceLock<String> = OnceLock::new();
+static CACHE: OnceLock<HttpCache> = OnceLock::new();
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// An error that occurred while performing an HTTP request.
+#[derive(Debug)]
+pub enum HttpError {
+    /// The shared client could not be built.
+    Client(reqwest::Error),
+    /// The URL could not be parsed.
+    InvalidUrl(reqwest::Error),
+    /// The request could not be sent or its body could not be read.
+    Request { url: Url, source: reqwest::Error },
+    /// The server responded with a non-success status code.
+    Status { url: Url, status: StatusCode },
+    /// The response could not be written to disk.
+    Io { path: PathBuf, source: io::Error },
+    /// The downloaded file does not have the expected size.
+    SizeMismatch {
+        url: String,
+        expected: u64,
+        actual: u64,
+    },
+    /// The downloaded file does not have the expected SHA-1 hash.
+    HashMismatch {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+    /// Offline mode is enabled and the response is not cached.
+    Offline { url: String },
+}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Client(err) => write!(f, "failed building http client: {err}"),
+            HttpError::InvalidUrl(err) => write!(f, "invalid url: {err}"),
+            HttpError::Request { url, source } => write!(f, "request to {url} failed: {source}"),
+            HttpError::Status { url, status } => write!(f, "{url} responded with {status}"),
+            HttpError::Io { path, source } => {
+                write!(f, "failed writing response to {}: {source}", path.display())
+            }
+            HttpError::SizeMismatch { url, expected, actual } => {
+                write!(f, "{url} has {actual} bytes, expected {expected}")
+            }
+            HttpError::HashMismatch { url, expected, actual } => {
+                write!(f, "{url} has sha1 {actual}, expected {expected}")
+            }
+            HttpError::Offline { url } => {
+                write!(f, "{url} is not cached and offline mode is enabled")
+            }
+        }
+    }
+}
+
+impl std::error::Error for HttpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HttpError::Client(err) | HttpError::InvalidUrl(err) => Some(err),
+            HttpError::Request { source, .. } => Some(source),
+            HttpError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl HttpError {
+    /// Whether retrying the request might succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            HttpError::Request { source, .. } => {
+                source.is_timeout() || source.is_connect() || source.is_request() || source.is_body()
+            }
+            HttpError::Status { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Overrides the user agent of the shared client.
+///
+/// This only has an effect before the first request is made.
+///
+/// # Returns
+///
+/// Whether the user agent was set.
+pub fn set_user_agent<S: Into<String>>(user_agent: S) -> bool {
+    CLIENT.get().is_none() && USER_AGENT.set(user_agent.into()).is_ok()
+}
+
+/// The user agent sent with every request.
+pub fn user_agent() -> &'static str {
+    USER_AGENT
+        .get()
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_USER_AGENT)
+}
+
+/// Enables the on-disk response cache in `dir`.
+///
+/// # Returns
+///
+/// Whether the cache was enabled. It can only be set once.
+pub fn set_cache_dir<P: Into<PathBuf>>(dir: P) -> bool {
+    CACHE.set(HttpCache::new(dir)).is_ok()
+}
+
+/// The on-disk response cache, if enabled.
+pub fn cache() -> Option<&'static HttpCache> {
+    CACHE.get()
+}
+
+/// Enables or disables offline mode. While offline, every response is served
+/// from the cache and no request is sent.
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+/// Whether offline mode is enabled.
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// Returns the shared client, building it on first use.
+pub fn client() -> Result<&'static Client, HttpError> {
+    if let Some(client) = CLIENT.get() {
+        return Ok(client);
+    }
+
+    let client = Client::builder()
+        .user_agent(user_agent())
+        .connect_timeout(CONNECT_TIMEOUT)
+        .read_timeout(READ_TIMEOUT)
+        .build()
+        .map_err(HttpError::Client)?;
+
+    Ok(CLIENT.get_or_init(|| client))
+}
+
+/// Sends a request built by `build`, retrying with exponential backoff on
+/// network errors and server errors.
+///
+/// # Arguments
+///
+/// * `url` - The URL to request.
+/// * `build` - Adds headers or other options to the request of every attempt.
+///
+/// # Returns
+///
+/// The first successful response.
+pub async fn send<F>(url: &Url, build: F) -> Result<Response, HttpError>
+where
+    F: Fn(RequestBuilder) -> RequestBuilder,
+{
+    retry(url, || send_once(url, &build)).await
+}
+
+async fn execute<F>(url: &Url, build: &F) -> Result<Response, HttpError>
+where
+    F: Fn(RequestBuilder) -> RequestBuilder,
+{
+    if is_offline() {
+        return Err(HttpError::Offline {
+            url: url.to_string(),
+        });
+    }
+
+    build(client()?.get(url.clone()))
+        .send()
+        .await
+        .map_err(|source| HttpError::Request {
+            url: url.clone(),
+            source,
+        })
+}
+
+async fn send_once<F>(url: &Url, build: &F) -> Result<Response, HttpError>
+where
+    F: Fn(RequestBuilder) -> RequestBuilder,
+{
+    check_status(url, execute(url, build).await?)
+}
+
+/// Sends a request that is conditional on `cached` being outdated.
+///
+/// # Returns
+///
+/// `None` if the server reported that the cached response is still fresh.
+async fn send_conditional(
+    url: &Url,
+    cached: Option<&CacheEntry>,
+) -> Result<Option<Response>, HttpError> {
+    let response = execute(url, &|request| match cached {
+        Some(entry) => entry.apply(request),
+        None => request,
+    })
+    .await?;
+
+    if cached.is_some() && response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+
+    check_status(url, response).map(Some)
+}
+
+/// Runs `attempt` until it succeeds, fails with a non-transient error or
+/// [`MAX_ATTEMPTS`] is reached.
+pub(crate) async fn retry<T, F, Fut>(url: &Url, mut attempt: F) -> Result<T, HttpError>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, HttpError>>,
+{
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempts = 1;
+
+    loop {
+        match attempt().await {
+            Err(err) if err.is_transient() && attempts < MAX_ATTEMPTS => {
+                warn!("Attempt {attempts}/{MAX_ATTEMPTS} for {url} failed: {err}. Retrying in {backoff:?}...");
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempts += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn check_status(url: &Url, response: Response) -> Result<Response, HttpError> {
+    if response.status().is_success() {
+        Ok(response)
+    } else {
+        Err(HttpError::Status {
+            url: url.clone(),
+            status: response.status(),
+        })
+    }
+}
+
+pub(crate) fn parse_url<U: IntoUrl>(url: U) -> Result<Url, HttpError> {
+    url.into_url().map_err(HttpError::InvalidUrl)
+}
+
+fn read_cached(cache: &HttpCache, url: &Url) -> Result<String, HttpError> {
+    cache.read(url).map_err(|source| HttpError::Io {
+        path: cache.body_path(url),
+        source,
+    })
+}
+
+/// Fetches a URL and returns its body as text.
+///
+/// If the cache is enabled, a cached response is revalidated with the server
+/// and only downloaded again if it changed. In offline mode, the cached
+/// response is returned as is.
+pub async fn get_url<U: IntoUrl>(url: U) -> error::Result<String> {
+    Ok(get_url_with(parse_url(url)?, cache(), is_offline()).await?)
+}
+
+/// Like [`get_url`], with an explicit cache and offline mode instead of the
+/// global ones.
+pub(crate) async fn get_url_with(
+    url: Url,
+    cache: Option<&HttpCache>,
+    offline: bool,
+) -> Result<String, HttpError> {
+    let cached = cache.and_then(|cache| cache.lookup(&url));
+
+    match (cache, &cached, offline) {
+        (Some(cache), Some(_), true) => return read_cached(cache, &url),
+        (_, _, true) => {
+            return Err(HttpError::Offline {
+                url: url.to_string(),
+            })
+        }
+        _ => {}
+    }
+
+    retry(&url, || async {
+        let response = match (send_conditional(&url, cached.as_ref()).await?, cache) {
+            (Some(response), _) => response,
+            (None, Some(cache)) => return read_cached(cache, &url),
+            (None, None) => {
+                return Err(HttpError::Status {
+                    url: url.clone(),
+                    status: StatusCode::NOT_MODIFIED,
+                })
+            }
+        };
+
+        let entry = CacheEntry::from_headers(&url, response.headers());
+        let body = response.text().await.map_err(|source| HttpError::Request {
+            url: url.clone(),
+            source,
+        })?;
+
+        if let Some(cache) = cache {
+            if let Err(err) = cache.store(&entry, &url, &body) {
+                warn!("Failed caching {url}: {err}");
+            }
+        }
+
+        Ok(body)
+    })
+    .await
+}
+
+/// What a downloaded file is expected to look like.
+///
+/// Fields that are `None` are not checked.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Integrity {
+    /// The lowercase hex SHA-1 hash of the file.
+    pub sha1: Option<String>,
+    /// The size of the file in bytes.
+    pub size: Option<u64>,
+}
+
+impl Integrity {
+    pub fn sha1<S: Into<String>>(sha1: S) -> Self {
+        Self {
+            sha1: Some(sha1.into().to_lowercase()),
+            size: None,
+        }
+    }
+
+    pub fn with_size(mut self, size: u64) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    /// Whether there is anything to check at all.
+    pub fn is_empty(&self) -> bool {
+        self.sha1.is_none() && self.size.is_none()
+    }
+
+    /// Checks a file on disk against the expected size and hash.
+    pub fn verify(&self, url: &Url, path: &Path) -> Result<(), HttpError> {
+        let io_error = |source| HttpError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+
+        if let Some(expected) = self.size {
+            let actual = fs::metadata(path).map_err(io_error)?.len();
+            if actual != expected {
+                return Err(HttpError::SizeMismatch {
+                    url: url.to_string(),
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        if let Some(expected) = &self.sha1 {
+            let actual = hash_file::<Sha1>(path).map_err(io_error)?;
+            if actual != *expected {
+                return Err(HttpError::HashMismatch {
+                    url: url.to_string(),
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Downloads a URL into a file.
+///
+/// # Arguments
+///
+/// * `url` - The URL to download.
+/// * `path` - The path the body should be written to.
+pub async fn download_url<U: IntoUrl, P: AsRef<Path>>(url: U, path: P) -> error::Result<()> {
+    download_verified(url, path, &Integrity::default()).await
+}
+
+/// Downloads a URL into a file and checks it against `integrity`.
+///
+/// The body is written to `{path}.part` first, which is only renamed to `path`
+/// once it has been verified. If a partial file is left over from an earlier,
+/// interrupted attempt, the download resumes from its end using an HTTP Range
+/// request, if the file has not changed since. If `path` already exists and matches a non-empty `integrity`,
+/// nothing is downloaded. Like [`get_url`], this goes through the response
+/// cache if it is enabled.
+///
+/// # Arguments
+///
+/// * `url` - The URL to download.
+/// * `path` - The path the verified file should end up at.
+/// * `integrity` - The expected size and hash of the file.
+pub async fn download_verified<U: IntoUrl, P: AsRef<Path>>(
+    url: U,
+    path: P,
+    integrity: &Integrity,
+) -> error::Result<()> {
+    Ok(download_verified_with(parse_url(url)?, path.as_ref(), integrity, cache(), is_offline()).await?)
+}
+
+/// Like [`download_verified`], with an explicit cache and offline mode instead
+/// of the global ones.
+pub(crate) async fn download_verified_with(
+    url: Url,
+    path: &Path,
+    integrity: &Integrity,
+    cache: Option<&HttpCache>,
+    offline: bool,
+) -> Result<(), HttpError> {
+
+    if !integrity.is_empty() && path.exists() && integrity.verify(&url, path).is_ok() {
+        info!("{} is already up to date", path.display());
+        return Ok(());
+    }
+
+    let mut part_name = path.as_os_str().to_owned();
+    part_name.push(".part");
+    let part = PathBuf::from(part_name);
+
+    let cached = cache.and_then(|cache| cache.lookup(&url));
+
+    let fetched = match (cache, &cached, offline) {
+        (Some(_), Some(_), true) => None,
+        (_, _, true) => {
+            return Err(HttpError::Offline {
+                url: url.to_string(),
+            })
+        }
+        _ => retry(&url, || download_part(&url, &part, integrity, cached.as_ref())).await?,
+    };
+
+    match (fetched, cache) {
+        (Some(entry), cache) => {
+            if let Err(err) = integrity.verify(&url, &part) {
+                remove_part(&part);
+                return Err(err);
+            }
+
+            if let Some(cache) = cache {
+                if let Err(err) = cache.store_file(&entry, &url, &part) {
+                    warn!("Failed caching {url}: {err}");
+                }
+            }
+        }
+        (None, Some(cache)) => {
+            info!("Using cached {url}");
+            fs::copy(cache.body_path(&url), &part).map_err(|source| HttpError::Io {
+                path: part.clone(),
+                source,
+            })?;
+
+            if let Err(err) = integrity.verify(&url, &part) {
+                let _ = fs::remove_file(&part);
+                return Err(err);
+            }
+        }
+        (None, None) => {
+            return Err(HttpError::Offline {
+                url: url.to_string(),
+            })
+        }
+    }
+
+    let _ = fs::remove_file(part_validators_path(&part));
+    fs::rename(&part, path).map_err(|source| HttpError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// The validators of the response a partial download came from, stored next
+/// to it so a resumed download can be checked against them.
+fn part_validators_path(part: &Path) -> PathBuf {
+    let mut name = part.as_os_str().to_owned();
+    name.push(".json");
+    PathBuf::from(name)
+}
+
+fn read_part_validators(part: &Path) -> Option<CacheEntry> {
+    let json = fs::read_to_string(part_validators_path(part)).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+/// Removes a partial download and its validators.
+fn remove_part(part: &Path) {
+    let _ = fs::remove_file(part);
+    let _ = fs::remove_file(part_validators_path(part));
+}
+
+/// Downloads the remainder of `url` into `part`, resuming where a previous
+/// attempt left off.
+///
+/// A download is only resumed with an `If-Range` request carrying the
+/// validator of the response the partial file came from, and only if the
+/// server answers with the requested range. Otherwise it starts over, so parts
+/// of different versions of a file are never joined.
+///
+/// # Returns
+///
+/// The validators of the response, or `None` if `cached` is still fresh.
+async fn download_part(
+    url: &Url,
+    part: &Path,
+    integrity: &Integrity,
+    cached: Option<&CacheEntry>,
+) -> Result<Option<CacheEntry>, HttpError> {
+    let io_error = |source| HttpError::Io {
+        path: part.to_path_buf(),
+        source,
+    };
+
+    let mut offset = fs::metadata(part).map(|meta| meta.len()).unwrap_or(0);
+    if integrity.size.is_some_and(|size| offset > size) {
+        offset = 0;
+    }
+    if offset > 0 && integrity.size == Some(offset) {
+        return Ok(Some(CacheEntry {
+            url: url.to_string(),
+            ..Default::default()
+        }));
+    }
+
+    let validator = read_part_validators(part)
+        .and_then(|entry| entry.if_range().map(str::to_owned))
+        .filter(|_| offset > 0);
+    if offset > 0 && validator.is_none() {
+        warn!("Cannot resume {url} without a validator, starting over");
+        remove_part(part);
+    }
+
+    let mut response = if let Some(validator) = &validator {
+        let range = |request: RequestBuilder| {
+            request
+                .header(RANGE, format!("bytes={offset}-"))
+                .header(IF_RANGE, validator)
+        };
+        match send_once(url, &range).await {
+            Err(HttpError::Status { status, .. }) if status == StatusCode::RANGE_NOT_SATISFIABLE => {
+                // The partial file is unusable, start over.
+                remove_part(part);
+                send_once(url, &|request| request).await?
+            }
+            result => result?,
+        }
+    } else {
+        match send_conditional(url, cached).await? {
+            Some(response) => response,
+            None => return Ok(None),
+        }
+    };
+
+    let expected_range = format!("bytes {offset}-");
+    let resumed = response.status() == StatusCode::PARTIAL_CONTENT && validator.is_some();
+    if resumed
+        && !response
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|range| range.to_str().ok())
+            .is_some_and(|range| range.starts_with(&expected_range))
+    {
+        warn!("{url} did not resume at byte {offset}, starting over");
+        drop(response);
+        remove_part(part);
+        response = send_once(url, &|request| request).await?;
+    }
+
+    let entry = CacheEntry::from_headers(url, response.headers());
+    let mut file = if response.status() == StatusCode::PARTIAL_CONTENT && validator.is_some() {
+        info!("Resuming download of {url} at byte {offset}");
+        OpenOptions::new().append(true).open(part)
+    } else {
+        let validators = part_validators_path(part);
+        if entry.if_range().is_some() {
+            let json = serde_json::to_string(&entry).unwrap_or_default();
+            fs::write(&validators, json).map_err(io_error)?;
+        } else {
+            let _ = fs::remove_file(&validators);
+        }
+        File::create(part)
+    }
+    .map_err(io_error)?;
+
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|source| HttpError::Request {
+            url: url.clone(),
+            source,
+        })?;
+
+        file.write_all(&chunk).map_err(io_error)?;
+    }
+
+    file.flush().map_err(io_error)?;
+
+    Ok(Some(entry))
+}
@@ -0,0 +1,322 @@
+//! The `index.json` listing every version built into a run directory.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use chrono::Utc;
+use proc_macros::serial_snake;
+use tracing::warn;
+
+use crate::checksums;
+use crate::config::PatchedVersionMeta;
+use crate::error::{Error, Result};
+use crate::history::{self, ArchivedBuild};
+use crate::signing::Signer;
+use crate::util::{sha1, timestamp, write_atomic};
+use crate::version::schema::spigot::SpigotVersionRefs;
+use crate::MinecraftVersion;
+
+/// The name of the index file in the run directory.
+pub const INDEX_FILE: &str = "index.json";
+
+/// The version of the [`Index`] format. It is increased whenever the format
+/// changes in a way older consumers cannot read.
+pub const INDEX_SCHEMA_VERSION: u32 = 1;
+
+/// The entry point of a run directory.
+#[serial_snake]
+pub struct Index {
+    pub schema_version: u32,
+    /// When the index was written, in RFC 3339.
+    pub generated_at: String,
+    /// The newest version in `versions`.
+    pub latest_release: Option<String>,
+    /// Every built version, oldest first.
+    pub versions: Vec<IndexEntry>,
+}
+
+/// A file of a version, relative to the run directory.
+#[serial_snake]
+pub struct IndexFile {
+    pub name: String,
+    pub sha1: String,
+    pub size: u64,
+}
+
+/// A built version.
+#[serial_snake]
+pub struct IndexEntry {
+    pub version: String,
+    /// The `{version}.json` metadata.
+    pub metadata: IndexFile,
+    /// The `{version}.patch` patch.
+    pub patch: IndexFile,
+    /// The legacy `{version}.libs` file.
+    pub libs: Option<IndexFile>,
+    /// The `{version}.libraries.json` manifest.
+    pub libraries: Option<IndexFile>,
+    pub commit_hashes: SpigotVersionRefs,
+    /// When the version was built, in RFC 3339.
+    pub built_at: String,
+    /// The builds superseded by newer Spigot refs, newest first.
+    #[serde(default)]
+    pub history: Vec<HistoryEntry>,
+}
+
+/// A superseded build of a version, kept in the history directory.
+#[serial_snake]
+pub struct HistoryEntry {
+    /// The hash of the patched jar, which names the build's directory.
+    pub patched_jar_hash: String,
+    pub metadata: IndexFile,
+    pub patch: IndexFile,
+    pub libs: Option<IndexFile>,
+    pub libraries: Option<IndexFile>,
+    pub commit_hashes: SpigotVersionRefs,
+    /// When the build was made, in RFC 3339.
+    pub built_at: String,
+}
+
+impl IndexFile {
+    fn of(run_dir: &Path, name: String) -> Result<Self> {
+        let path = run_dir.join(&name);
+        let size = fs::metadata(&path).map_err(Error::io(&path))?.len();
+
+        Ok(Self {
+            sha1: sha1(&path)?,
+            name,
+            size,
+        })
+    }
+
+    fn optional(run_dir: &Path, name: String) -> Result<Option<Self>> {
+        if run_dir.join(&name).is_file() {
+            Self::of(run_dir, name).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl IndexEntry {
+    /// The files of the version, without its history.
+    pub fn files(&self) -> impl Iterator<Item = &IndexFile> {
+        [Some(&self.metadata), Some(&self.patch), self.libs.as_ref(), self.libraries.as_ref()]
+            .into_iter()
+            .flatten()
+    }
+}
+
+impl HistoryEntry {
+    fn of(run_dir: &Path, version: &str, build: ArchivedBuild) -> Result<Self> {
+        Ok(Self {
+            patched_jar_hash: build.meta.patched_jar_hash.clone(),
+            metadata: IndexFile::of(run_dir, build.file(&format!("{version}.json")))?,
+            patch: IndexFile::of(run_dir, build.file(&build.meta.patch_file))?,
+            libs: IndexFile::optional(run_dir, build.file(&format!("{version}.libs")))?,
+            libraries: IndexFile::optional(run_dir, build.file(&format!("{version}.libraries.json")))?,
+            built_at: timestamp(build.built_at),
+            commit_hashes: build.meta.commit_hashes,
+        })
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &IndexFile> {
+        [Some(&self.metadata), Some(&self.patch), self.libs.as_ref(), self.libraries.as_ref()]
+            .into_iter()
+            .flatten()
+    }
+}
+
+impl Index {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(Error::io(path))?;
+
+        serde_json::from_str(&content).map_err(Error::metadata(path.display().to_string()))
+    }
+
+    /// Writes the index atomically.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let json = serde_json::to_string_pretty(self)
+            .map_err(Error::metadata(path.display().to_string()))?;
+
+        write_atomic(path, json)
+    }
+
+    /// Lists every version with metadata in a run directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `run_dir` - The run directory.
+    /// * `previous` - The previous index, whose build timestamps are kept, and
+    ///   its generation timestamp too if no version changed.
+    /// * `built` - The versions built just now.
+    pub fn generate(
+        run_dir: &Path,
+        previous: Option<&Index>,
+        built: &HashSet<String>,
+    ) -> Result<Self> {
+        let now = Utc::now();
+        let built_at = previous
+            .map(|index| {
+                index
+                    .versions
+                    .iter()
+                    .map(|entry| (entry.version.as_str(), entry.built_at.as_str()))
+                    .collect::<HashMap<_, _>>()
+            })
+            .unwrap_or_default();
+
+        let mut versions = vec![];
+        for file in fs::read_dir(run_dir).map_err(Error::io(run_dir))? {
+            let path = file.map_err(Error::io(run_dir))?.path();
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+            // `index.json` and `{version}.libraries.json` are not versions.
+            let Some(version) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let Ok(minecraft_version) = MinecraftVersion::of(version.to_owned()) else {
+                continue;
+            };
+
+            let meta = match PatchedVersionMeta::read(&path) {
+                Ok(meta) => meta,
+                Err(err) => {
+                    warn!("Leaving {version} out of the index: {err}");
+                    continue;
+                }
+            };
+            if !run_dir.join(&meta.patch_file).is_file() {
+                warn!("Leaving {version} out of the index: {} is missing", meta.patch_file);
+                continue;
+            }
+
+            let built_at = if built.contains(version) {
+                timestamp(now)
+            } else if let Some(built_at) = built_at.get(version) {
+                built_at.to_string()
+            } else {
+                let modified = fs::metadata(&path)
+                    .and_then(|metadata| metadata.modified())
+                    .map_err(Error::io(&path))?;
+                timestamp(modified.into())
+            };
+
+            let entry = IndexEntry {
+                version: version.to_owned(),
+                metadata: IndexFile::of(run_dir, format!("{version}.json"))?,
+                patch: IndexFile::of(run_dir, meta.patch_file.clone())?,
+                libs: IndexFile::optional(run_dir, format!("{version}.libs"))?,
+                libraries: IndexFile::optional(run_dir, format!("{version}.libraries.json"))?,
+                commit_hashes: meta.commit_hashes,
+                built_at,
+                history: history::builds(run_dir, version)?
+                    .into_iter()
+                    .map(|build| HistoryEntry::of(run_dir, version, build))
+                    .collect::<Result<_>>()?,
+            };
+            versions.push((minecraft_version, entry));
+        }
+
+        versions.sort_by_key(|(version, _)| *version);
+        let versions = versions.into_iter().map(|(_, entry)| entry).collect::<Vec<_>>();
+
+        // An unchanged index is left as it was, so it is not committed again.
+        let generated_at = match previous {
+            Some(previous) if previous.schema_version == INDEX_SCHEMA_VERSION && previous.versions == versions => {
+                previous.generated_at.clone()
+            }
+            _ => timestamp(now),
+        };
+
+        Ok(Self {
+            schema_version: INDEX_SCHEMA_VERSION,
+            generated_at,
+            latest_release: versions.last().map(|entry| entry.version.clone()),
+            versions,
+        })
+    }
+}
+
+/// Regenerates the index of a run directory, keeping the build timestamps of
+/// the previous index.
+///
+/// # Arguments
+///
+/// * `run_dir` - The run directory.
+/// * `built` - The versions built just now.
+pub fn update<P: AsRef<Path>>(run_dir: P, built: &HashSet<String>) -> Result<Index> {
+    let run_dir = run_dir.as_ref();
+    let path = run_dir.join(INDEX_FILE);
+
+    let previous = if path.exists() {
+        Index::read(&path)
+            .inspect_err(|err| warn!("Ignoring the previous index: {err}"))
+            .ok()
+    } else {
+        None
+    };
+
+    let index = Index::generate(run_dir, previous.as_ref(), built)?;
+    index.write(&path)?;
+    Ok(index)
+}
+
+/// Regenerates the index and the checksum manifests of a run directory and,
+/// with a `signer`, signs them and publishes its public key.
+///
+/// # Arguments
+///
+/// * `run_dir` - The run directory.
+/// * `built` - The versions built just now.
+/// * `signer` - The key to sign with, if any.
+pub fn finalize<P: AsRef<Path>>(
+    run_dir: P,
+    built: &HashSet<String>,
+    signer: Option<&Signer>,
+) -> Result<Index> {
+    let run_dir = run_dir.as_ref();
+    let index = update(run_dir, built)?;
+    let manifests = checksums::write_manifests(run_dir, &index)?;
+
+    if let Some(signer) = signer {
+        signer.sign_file(run_dir.join(INDEX_FILE))?;
+        for manifest in manifests {
+            signer.sign_file(manifest)?;
+        }
+        signer.publish_public_key(run_dir)?;
+    }
+
+    Ok(index)
+}
+
+/// Reads the index of a run directory and checks the SHA-1 hash of every file
+/// it lists, including the history.
+///
+/// # Errors
+///
+/// Fails on the first file that is missing or does not match.
+pub fn verify<P: AsRef<Path>>(run_dir: P) -> Result<Index> {
+    let run_dir = run_dir.as_ref();
+    let index = Index::read(run_dir.join(INDEX_FILE))?;
+
+    for entry in &index.versions {
+        let history = entry.history.iter().flat_map(|build| build.files());
+        for IndexFile { name, sha1: expected, .. } in entry.files().chain(history) {
+            let path = run_dir.join(name);
+            let actual = sha1(&path)?;
+            if !actual.eq_ignore_ascii_case(expected) {
+                return Err(Error::verification(
+                    &path,
+                    format!("has sha1 {actual}, but the index lists {expected}"),
+                ));
+            }
+        }
+    }
+
+    Ok(index)
+}
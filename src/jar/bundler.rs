@@ -0,0 +1,221 @@
+//! Reads Mojang's bundler jars.
+//!
+//! Since 1.18, vanilla and Spigot server jars are bundlers: the actual server
+//! jar and its libraries are nested inside `META-INF/versions/` and
+//! `META-INF/libraries/`, listed in `META-INF/versions.list` and
+//! `META-INF/libraries.list`.
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+use zip::read::ZipFile;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+use crate::error::{Error, Result};
+use crate::jar::open_jar;
+
+/// The list of nested server jars.
+pub const VERSIONS_LIST: &str = "META-INF/versions.list";
+/// The list of nested libraries.
+pub const LIBRARIES_LIST: &str = "META-INF/libraries.list";
+/// The file containing the main class of the nested server jar.
+pub const MAIN_CLASS: &str = "META-INF/main-class";
+/// The directory the paths in [`VERSIONS_LIST`] are relative to.
+pub const VERSIONS_DIR: &str = "META-INF/versions/";
+/// The directory the paths in [`LIBRARIES_LIST`] are relative to.
+pub const LIBRARIES_DIR: &str = "META-INF/libraries/";
+
+/// A line of `versions.list` or `libraries.list`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BundlerEntry {
+    /// The SHA-256 hash of the nested jar.
+    pub hash: String,
+    /// The id of the nested jar, a Maven coordinate for libraries.
+    pub id: String,
+    /// The path of the nested jar, relative to its list's directory.
+    pub path: String,
+}
+
+impl BundlerEntry {
+    /// Parses the tab separated lines of a bundler list.
+    ///
+    /// # Arguments
+    ///
+    /// * `jar` - The jar the list was read from, for error messages.
+    /// * `list` - The name of the list, for error messages.
+    /// * `content` - The content of the list.
+    pub fn parse_list(jar: &Path, list: &str, content: &str) -> Result<Vec<Self>> {
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| match line.trim().split('\t').collect::<Vec<_>>()[..] {
+                [hash, id, path] => Ok(BundlerEntry {
+                    hash: hash.to_owned(),
+                    id: id.to_owned(),
+                    path: path.to_owned(),
+                }),
+                _ => Err(Error::jar(jar, format!("malformed line in {list}: {line:?}"))),
+            })
+            .collect()
+    }
+}
+
+/// An opened bundler jar.
+pub struct Bundler {
+    path: PathBuf,
+    zip: ZipArchive<File>,
+    versions: Vec<BundlerEntry>,
+    libraries: Vec<BundlerEntry>,
+    main_class: Option<String>,
+}
+
+impl Bundler {
+    /// Opens a jar and reads its bundler metadata.
+    ///
+    /// # Returns
+    ///
+    /// The bundler, or `None` if the jar has no `versions.list` and therefore is
+    /// a plain server jar.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Option<Self>> {
+        let path = path.as_ref().to_path_buf();
+        let mut zip = open_jar(&path)?;
+
+        let Some(versions) = read_text(&path, &mut zip, VERSIONS_LIST)? else {
+            return Ok(None);
+        };
+        let versions = BundlerEntry::parse_list(&path, VERSIONS_LIST, &versions)?;
+
+        let libraries = match read_text(&path, &mut zip, LIBRARIES_LIST)? {
+            Some(libraries) => BundlerEntry::parse_list(&path, LIBRARIES_LIST, &libraries)?,
+            None => vec![],
+        };
+
+        let main_class = read_text(&path, &mut zip, MAIN_CLASS)?
+            .map(|main_class| main_class.trim().to_owned())
+            .filter(|main_class| !main_class.is_empty());
+
+        Ok(Some(Self {
+            path,
+            zip,
+            versions,
+            libraries,
+            main_class,
+        }))
+    }
+
+    /// The path of the bundler jar.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The nested server jars.
+    pub fn versions(&self) -> &[BundlerEntry] {
+        &self.versions
+    }
+
+    /// The nested libraries.
+    pub fn libraries(&self) -> &[BundlerEntry] {
+        &self.libraries
+    }
+
+    /// The main class of the nested server jar.
+    pub fn main_class(&self) -> Option<&str> {
+        self.main_class.as_deref()
+    }
+
+    /// The nested server jar. Bundlers contain exactly one.
+    pub fn server(&self) -> Result<&BundlerEntry> {
+        self.versions
+            .first()
+            .ok_or_else(|| Error::jar(&self.path, format!("{VERSIONS_LIST} is empty")))
+    }
+
+    /// Opens a nested server jar as a stream.
+    pub fn open_version(&mut self, entry: &BundlerEntry) -> Result<ZipFile<'_>> {
+        self.open_entry(&format!("{VERSIONS_DIR}{}", entry.path))
+    }
+
+    /// Opens a nested library as a stream.
+    pub fn open_library(&mut self, entry: &BundlerEntry) -> Result<ZipFile<'_>> {
+        self.open_entry(&format!("{LIBRARIES_DIR}{}", entry.path))
+    }
+
+    fn open_entry(&mut self, name: &str) -> Result<ZipFile<'_>> {
+        self.zip
+            .by_name(name)
+            .map_err(|e| Error::jar(&self.path, format!("{name}: {e}")))
+    }
+
+    /// Writes the nested server jar to a directory, keeping its relative path,
+    /// and checks it against the hash in `versions.list`.
+    ///
+    /// # Returns
+    ///
+    /// The path of the written jar.
+    pub fn extract_server<P: AsRef<Path>>(&mut self, directory: P) -> Result<PathBuf> {
+        let entry = self.server()?.clone();
+        let output = directory.as_ref().join(&entry.path);
+        if let Some(parent) = output.parent() {
+            fs::create_dir_all(parent).map_err(Error::io(parent))?;
+        }
+
+        let mut file = File::create(&output).map_err(Error::io(&output))?;
+        let mut hasher = Sha256::new();
+        {
+            let mut stream = self.open_version(&entry)?;
+            let mut buffer = [0u8; 64 * 1024];
+            loop {
+                let read = stream.read(&mut buffer).map_err(Error::io(&output))?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..read]);
+                file.write_all(&buffer[..read]).map_err(Error::io(&output))?;
+            }
+        }
+
+        let actual = hex::encode(hasher.finalize());
+        if !actual.eq_ignore_ascii_case(&entry.hash) {
+            return Err(Error::jar(
+                &self.path,
+                format!("{} has sha256 {actual}, expected {}", entry.path, entry.hash),
+            ));
+        }
+
+        Ok(output)
+    }
+}
+
+/// Reads a text file from a zip, or `None` if it does not exist.
+fn read_text(jar: &Path, zip: &mut ZipArchive<File>, name: &str) -> Result<Option<String>> {
+    let mut file = match zip.by_name(name) {
+        Ok(file) => file,
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(Error::jar(jar, format!("{name}: {e}"))),
+    };
+
+    let mut content = String::new();
+    file.read_to_string(&mut content)
+        .map_err(|e| Error::jar(jar, format!("{name}: {e}")))?;
+
+    Ok(Some(content))
+}
+
+/// Returns the actual server jar of a jar that may be a bundler.
+///
+/// If `jar` is a bundler, its nested server jar is written to `directory`
+/// without extracting anything else. Otherwise `jar` itself is the server jar.
+///
+/// # Arguments
+///
+/// * `jar` - The vanilla or Spigot jar.
+/// * `directory` - The directory a nested server jar is written to.
+pub fn unbundle<P: AsRef<Path>, P1: AsRef<Path>>(jar: P, directory: P1) -> Result<PathBuf> {
+    match Bundler::open(&jar)? {
+        Some(mut bundler) => bundler.extract_server(directory),
+        None => Ok(jar.as_ref().to_path_buf()),
+    }
+}
@@ -0,0 +1,182 @@
+//! Finds libraries that are already embedded in a server jar.
+//!
+//! Spigot shades some of its dependencies into the server jar, and since 1.18
+//! ships others in the bundler's `libraries.list`. Putting them on the
+//! classpath again would load their classes twice.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use proc_macros::serial_snake;
+use tracing::info;
+
+use crate::error::{Error, Result};
+use crate::jar::bundler::BundlerEntry;
+use crate::jar::open_jar;
+use crate::maven::MavenDependency;
+
+/// How a library was found inside a jar.
+#[serial_snake]
+#[derive(Copy, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Embedding {
+    /// The jar contains the library's `META-INF/maven/{group}/{artifact}/pom.properties`.
+    PomProperties,
+    /// The jar contains most classes of the library, without relocation.
+    ShadedClasses,
+    /// The bundler's `libraries.list` ships the library, in any version.
+    Bundled,
+}
+
+/// The artifacts and classes inside a jar.
+pub struct EmbeddedArtifacts {
+    path: PathBuf,
+    /// The versions of the artifacts with a `pom.properties`, by `group:artifact`.
+    artifacts: HashMap<String, String>,
+    classes: HashSet<String>,
+    /// The `group:artifact` of the libraries the bundler ships.
+    bundled: HashSet<String>,
+}
+
+impl EmbeddedArtifacts {
+    /// Lists the artifacts and classes of a jar.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The jar.
+    /// * `bundled` - The `libraries.list` entries of the bundler the jar came from.
+    pub fn scan<P: AsRef<Path>>(path: P, bundled: &[BundlerEntry]) -> Result<Self> {
+        let path = path.as_ref();
+        let mut zip = open_jar(path)?;
+        let mut artifacts = HashMap::new();
+        let mut classes = HashSet::new();
+
+        for index in 0..zip.len() {
+            let mut file = zip
+                .by_index(index)
+                .map_err(|e| Error::jar(path, format!("entry {index}: {e}")))?;
+            let name = file.name().to_owned();
+
+            if name.ends_with(".class") {
+                classes.insert(name);
+            } else if name.starts_with("META-INF/maven/") && name.ends_with("/pom.properties") {
+                let mut content = String::new();
+                file.read_to_string(&mut content)
+                    .map_err(|e| Error::jar(path, format!("{name}: {e}")))?;
+
+                let properties = parse_properties(&content);
+                if let (Some(group), Some(artifact), Some(version)) = (
+                    properties.get("groupId"),
+                    properties.get("artifactId"),
+                    properties.get("version"),
+                ) {
+                    artifacts.insert(format!("{group}:{artifact}"), version.clone());
+                }
+            }
+        }
+
+        let bundled = bundled
+            .iter()
+            .filter_map(|entry| {
+                let mut parts = entry.id.split(':');
+                Some(format!("{}:{}", parts.next()?, parts.next()?))
+            })
+            .collect();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            artifacts,
+            classes,
+            bundled,
+        })
+    }
+
+    /// Works out whether a dependency is embedded in the jar.
+    ///
+    /// A dependency is embedded if the jar has its `pom.properties` or the
+    /// bundler ships it, in any version, or if most classes of its jar in the
+    /// local repository are in the jar under the same names.
+    ///
+    /// # Arguments
+    ///
+    /// * `dependency` - The dependency.
+    /// * `local_repository` - The local Maven repository the dependency's jar is read from.
+    pub fn find(
+        &self,
+        dependency: &MavenDependency,
+        local_repository: Option<&Path>,
+    ) -> Result<Option<Embedding>> {
+        let key = format!("{}:{}", dependency.group_id, dependency.artifact_id);
+        if self.artifacts.contains_key(&key) {
+            return Ok(Some(Embedding::PomProperties));
+        }
+        if self.bundled.contains(&key) {
+            return Ok(Some(Embedding::Bundled));
+        }
+
+        let Some(local_repository) = local_repository else {
+            return Ok(None);
+        };
+        let jar = local_repository
+            .join(dependency.group_id.replace('.', "/"))
+            .join(&dependency.artifact_id)
+            .join(&dependency.version)
+            .join(dependency.file_name());
+        if !jar.is_file() {
+            return Ok(None);
+        }
+
+        let zip = open_jar(&jar)?;
+        let classes = zip
+            .file_names()
+            .filter(|name| name.ends_with(".class"))
+            .filter(|name| !name.ends_with("module-info.class") && !name.starts_with("META-INF/"))
+            .collect::<Vec<_>>();
+        let shaded = classes
+            .iter()
+            .filter(|class| self.classes.contains(**class))
+            .count();
+
+        if !classes.is_empty() && shaded * 2 > classes.len() {
+            Ok(Some(Embedding::ShadedClasses))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Sets [`MavenDependency::embedded`] for every dependency embedded in the jar.
+    pub fn mark(
+        &self,
+        dependencies: Vec<MavenDependency>,
+        local_repository: Option<&Path>,
+    ) -> Result<Vec<MavenDependency>> {
+        dependencies
+            .into_iter()
+            .map(|dependency| {
+                let embedded = self.find(&dependency, local_repository)?;
+                if let Some(embedded) = embedded {
+                    info!(
+                        "{dependency} is embedded in {} ({embedded:?})",
+                        self.path.display()
+                    );
+                }
+                Ok(MavenDependency {
+                    embedded,
+                    ..dependency
+                })
+            })
+            .collect()
+    }
+}
+
+/// Parses the `key=value` lines of a Java properties file, ignoring comments.
+fn parse_properties(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#') && !line.starts_with('!'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim().to_owned(), value.trim().to_owned()))
+        .collect()
+}
@@ -0,0 +1,67 @@
+//! Reads and extracts jar files.
+
+pub mod bundler;
+pub mod embedded;
+
+use std::{fs, io, path};
+use std::fs::File;
+use std::path::Path;
+use zip::ZipArchive;
+
+use crate::error::{Error, Result};
+
+/// Opens a jar file as a zip archive.
+pub fn open_jar<P: AsRef<Path>>(jar_file: P) -> Result<ZipArchive<File>> {
+    let jar_file = jar_file.as_ref();
+    let file_stream = File::open(jar_file).map_err(Error::io(jar_file))?;
+
+    ZipArchive::new(file_stream).map_err(|e| Error::jar(jar_file, e))
+}
+
+pub fn extract_jar<P: AsRef<Path>>(jar_file: P, output_directory: P) -> Result<()> {
+    let jar_file = jar_file.as_ref();
+    let mut zip = open_jar(jar_file)?;
+
+    for i in 0..zip.len() {
+        let mut zip_file = zip.by_index(i).map_err(|e| Error::jar(jar_file, e))?;
+        if let Some(enclosed_name) = zip_file.enclosed_name() {
+            let output_path = output_directory.as_ref().join(enclosed_name);
+            let output_path = path::absolute(&output_path).map_err(Error::io(&output_path))?;
+
+            if zip_file.is_dir() {
+                continue
+            }
+
+            if let Some(parent) = output_path.parent() {
+                fs::create_dir_all(parent).map_err(Error::io(parent))?;
+            }
+
+            let mut real_file = File::create(&output_path).map_err(Error::io(&output_path))?;
+            io::copy(&mut zip_file, &mut real_file).map_err(Error::io(&output_path))?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn has_dir<P: AsRef<Path>>(jar_file: P, file: &str) -> Result<bool> {
+    let jar_file = jar_file.as_ref();
+    let mut zip = open_jar(jar_file)?;
+
+    let file_name = path::absolute(file).map_err(Error::io(file))?;
+    for i in 0..zip.len() {
+        let zip_file = zip.by_index(i).map_err(|e| Error::jar(jar_file, e))?;
+        if let Some(enclosed_name) = zip_file.enclosed_name() {
+            let absolute_path = path::absolute(&enclosed_name).map_err(Error::io(&enclosed_name))?;
+            if !zip_file.is_dir() {
+                continue
+            }
+
+            if absolute_path == file_name {
+                return Ok(true);
+            }
+        }
+    }
+
+    Ok(false)
+}
@@ -0,0 +1,79 @@
+//! Discovers the JDKs BuildTools is run with.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use proc_macros::serial_snake;
+use tracing::warn;
+
+/// A JDK, as far as it could be identified.
+#[serial_snake]
+#[derive(Default)]
+pub struct JavaInstallation {
+    /// The `JAVA_HOME` of the JDK.
+    pub home: String,
+    /// The vendor, such as `Eclipse Adoptium`.
+    pub vendor: Option<String>,
+    /// The full version, such as `21.0.5`.
+    pub version: Option<String>,
+}
+
+impl JavaInstallation {
+    /// Identifies the JDK at a `JAVA_HOME`.
+    ///
+    /// The `release` file of the JDK is read first. If it does not name the
+    /// version, `java -XshowSettings:properties -version` is asked instead.
+    /// Anything that cannot be found out is left unset.
+    pub fn inspect<P: AsRef<Path>>(home: P) -> Self {
+        let home = home.as_ref();
+        let mut java = Self {
+            home: home.display().to_string(),
+            ..Default::default()
+        };
+
+        if let Ok(release) = fs::read_to_string(home.join("release")) {
+            let release = parse_release(&release);
+            java.vendor = release.get("IMPLEMENTOR").cloned();
+            java.version = release.get("JAVA_VERSION").cloned();
+        }
+        if java.version.is_some() {
+            return java;
+        }
+
+        let java_bin = home.join("bin/java");
+        match Command::new(&java_bin)
+            .args(["-XshowSettings:properties", "-version"])
+            .output()
+        {
+            Ok(output) => {
+                // The settings are printed to stderr.
+                let settings = parse_settings(&String::from_utf8_lossy(&output.stderr));
+                java.vendor = java.vendor.or_else(|| settings.get("java.vendor").cloned());
+                java.version = settings.get("java.version").cloned();
+            }
+            Err(err) => warn!("Could not identify the JDK at {}: {err}", home.display()),
+        }
+
+        java
+    }
+}
+
+/// Parses the `KEY="value"` lines of a JDK's `release` file.
+fn parse_release(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim().to_owned(), value.trim().trim_matches('"').to_owned()))
+        .collect()
+}
+
+/// Parses the `    key = value` lines printed by `-XshowSettings:properties`.
+fn parse_settings(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once(" = "))
+        .map(|(key, value)| (key.trim().to_owned(), value.trim().to_owned()))
+        .collect()
+}
@@ -1,35 +1,60 @@
+use crate::error::{Error, Result};
 use crate::util::{dir, sha1};
-use build_tools::{download_buildtools, find_file, run_buildtools, VANILLA_JAR_REGEX};
+use build_tools::{download_buildtools, find_file, run_buildtools, BuildToolsInfo, VANILLA_JAR_REGEX};
+use changelog::{ChangeKind, Changelog, VersionChange};
+use checksums::Checksums;
 use config::{Config, PatchedVersionMeta};
-use futures_util::StreamExt;
-use jar::extract_jar;
+use jar::bundler::{unbundle, Bundler, BundlerEntry};
+use jar::embedded::EmbeddedArtifacts;
+use java::JavaInstallation;
+use provenance::Provenance;
+use signing::Signer;
+use webhook::Notifier;
 use qbsdiff::{Bsdiff, Bspatch};
 use regex::Regex;
 use reqwest::IntoUrl;
 use scraper::Html;
+use std::collections::HashSet;
 use std::fmt::Display;
-use std::fs::File;
-use std::io::{Cursor, Read, Write};
+use std::io::Cursor;
 use std::path::{Path, PathBuf};
-use std::{fs, io};
+use std::sync::LazyLock;
+use std::fs;
+use std::time::{Duration, Instant};
 use tracing::{info, warn};
 use util::dir::create_temp_dir;
-use version::fetch_spigot_version_meta;
-use version::schema::spigot::SpigotBuildData;
+use version::schema::piston::{PistonVersionDownload, PistonVersionsResponse};
+use version::{fetch_piston_meta, fetch_piston_version_meta, fetch_spigot_version_meta};
+use version::schema::spigot::{SpigotBuildData, SpigotVersionMeta};
+use crate::libraries::LibrariesManifest;
 use crate::maven::MavenDependency;
 
 pub mod build_tools;
+pub mod changelog;
+pub mod checksums;
 pub mod config;
+pub mod daemon;
+pub mod error;
+pub mod history;
+pub mod http;
+pub mod index;
 pub mod jar;
+pub mod java;
+pub mod libraries;
+pub mod migrate;
+pub mod provenance;
+pub mod publish;
+pub mod server;
+pub mod signing;
+pub mod sink;
 #[cfg(test)]
 pub mod tests;
 pub mod util;
 pub mod version;
+pub mod webhook;
 pub mod maven;
 
-/// The user agent being used for all HTTP requests.
-pub const USER_AGENT: &str =
-    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:131.0) Gecko/20100101 Firefox/131.0";
+pub use http::{download_url, get_url};
 
 pub const JAR_VERSIONS_PATH: &str = "META-INF/versions/";
 
@@ -41,45 +66,12 @@ pub const SPIGOT_SERVER_JAR_REGEX: &str = r"spigot-(1\.\d{1,2}(?:\.\d{1,2})?)-R0
 
 pub const PISTON_DATA_BASE_URL: &str = "https://piston-data.mojang.com/v1/objects";
 
-pub type Reqwsult<T> = Result<T, reqwest::Error>;
+static VANILLA_JAR_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(VANILLA_JAR_REGEX).expect("VANILLA_JAR_REGEX is a valid regex"));
 
-pub async fn get_url<U: IntoUrl>(url: U) -> Reqwsult<String> {
-    let client = reqwest::Client::builder().user_agent(USER_AGENT).build()?;
-
-    client
-        .get(url)
-        .send()
-        .await
-        .expect("Failed to receive response")
-        .text()
-        .await
-}
-
-pub async fn download_url<U: IntoUrl, P: AsRef<Path>>(url: U, path: P) -> Reqwsult<()> {
-    let client = reqwest::Client::builder().user_agent(USER_AGENT).build()?;
-
-    let mut stream = client
-        .get(url)
-        .send()
-        .await
-        .expect("Failed to receive response")
-        .bytes_stream();
-
-    let mut buildtools_file = File::create(path).expect("Unable to create BuildTools file");
-
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk.expect("Failed to read bytes");
-        buildtools_file
-            .write_all(&chunk)
-            .expect("Failed to write to file");
-    }
-
-    Ok(())
-}
-
-pub async fn prepare_extraction_path(extraction_path: &Path) -> io::Result<()> {
+pub async fn prepare_extraction_path(extraction_path: &Path) -> Result<()> {
     if !extraction_path.exists() || !extraction_path.is_dir() {
-        fs::create_dir_all(extraction_path)?;
+        fs::create_dir_all(extraction_path).map_err(Error::io(extraction_path))?;
     } else {
         dir::clear_directory(extraction_path).await?;
     }
@@ -95,32 +87,31 @@ pub async fn prepare_extraction_path(extraction_path: &Path) -> io::Result<()> {
 /// # Returns
 ///
 /// The site's HTML.
-pub async fn fetch_url<U: IntoUrl>(url: U) -> Reqwsult<Html> {
+pub async fn fetch_url<U: IntoUrl>(url: U) -> Result<Html> {
     Ok(Html::parse_document(&(get_url(url).await?)))
 }
 
-pub fn write_patch<P, P1>(vanilla_jar: P, spigot_jar: P, out: P1) -> io::Result<()>
+pub fn write_patch<P, P1>(vanilla_jar: P, spigot_jar: P, out: P1) -> Result<()>
 where
     P: AsRef<Path>,
     P1: AsRef<Path>,
 {
-    let mut vanilla = File::open(vanilla_jar)?;
-    let mut vanilla_bytes = Vec::new();
-    vanilla.read_to_end(&mut vanilla_bytes)?;
-
-    let mut spigot = File::open(spigot_jar)?;
-    let mut spigot_bytes = Vec::new();
-    spigot.read_to_end(&mut spigot_bytes)?;
+    let out = out.as_ref();
+    let vanilla_bytes = fs::read(&vanilla_jar).map_err(Error::io(&vanilla_jar))?;
+    let spigot_bytes = fs::read(&spigot_jar).map_err(Error::io(&spigot_jar))?;
 
     let mut diff = Vec::new();
 
     Bsdiff::new(&vanilla_bytes, &spigot_bytes)
         .compression_level(9)
-        .compare(Cursor::new(&mut diff))?;
+        .compare(Cursor::new(&mut diff))
+        .map_err(|e| Error::patch(out, e))?;
 
-    fs::write(out, diff)
+    fs::write(out, diff).map_err(Error::io(out))
 }
 
+/// A release version, ordered semantically.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct MinecraftVersion(u8, u8, u8);
 
 impl Display for MinecraftVersion {
@@ -130,13 +121,18 @@ impl Display for MinecraftVersion {
 }
 
 impl MinecraftVersion {
-    pub fn of(string: String) -> Self {
+    pub fn of(string: String) -> Result<Self> {
         let numbers = string
             .split(".")
-            .map(|string| string.parse::<u8>().unwrap())
-            .collect::<Vec<u8>>();
-
-        Self(numbers[0], numbers[1], *numbers.get(2).unwrap_or(&0u8))
+            .map(|number| number.parse::<u8>())
+            .collect::<std::result::Result<Vec<u8>, _>>()
+            .map_err(|_| Error::InvalidVersion(string.clone()))?;
+
+        match numbers[..] {
+            [major, minor] => Ok(Self(major, minor, 0)),
+            [major, minor, patch] => Ok(Self(major, minor, patch)),
+            _ => Err(Error::InvalidVersion(string)),
+        }
     }
 
     pub fn get_java_version(&self) -> u8 {
@@ -156,168 +152,358 @@ impl MinecraftVersion {
     }
 }
 
+/// Resolves the official server download of a version from Mojang's Piston
+/// metadata and compares it against the vanilla jar BuildTools used.
+///
+/// # Arguments
+///
+/// * `piston_versions` - The Piston version manifest.
+/// * `version` - The Minecraft version.
+/// * `vanilla_jar_hash` - The SHA-1 hash of the vanilla jar BuildTools used.
+/// * `strict` - Whether a mismatch is an error instead of a warning.
+///
+/// # Returns
+///
+/// The server download, or `None` if Piston does not know the version.
+pub async fn check_vanilla_jar(
+    piston_versions: &PistonVersionsResponse,
+    version: &str,
+    vanilla_jar_hash: &str,
+    strict: bool,
+) -> Result<Option<PistonVersionDownload>> {
+    let Some(meta) = fetch_piston_version_meta(piston_versions, version).await? else {
+        return Ok(None);
+    };
+    let server = meta.downloads.server;
+
+    if server.sha1 == vanilla_jar_hash {
+        info!("Vanilla jar matches Piston metadata ({})", server.sha1);
+    } else if strict {
+        return Err(Error::VanillaMismatch {
+            version: version.to_owned(),
+            expected: server.sha1,
+            actual: vanilla_jar_hash.to_owned(),
+        });
+    } else {
+        warn!(
+            "Vanilla jar of {version} has sha1 {vanilla_jar_hash}, but Piston lists {}!",
+            server.sha1
+        );
+    }
+
+    Ok(Some(server))
+}
+
+/// Whether the output of a version is complete and was built from the refs
+/// Spigot lists for it now.
+///
+/// # Arguments
+///
+/// * `run_dir` - The run directory.
+/// * `version` - The version.
+/// * `remote_meta` - The metadata Spigot lists for the version.
+pub fn is_up_to_date(run_dir: &Path, version: &str, remote_meta: &SpigotVersionMeta) -> bool {
+    let complete = [format!("{version}.libs"), format!("{version}.libraries.json")]
+        .iter()
+        .all(|name| run_dir.join(name).exists());
+
+    complete
+        && PatchedVersionMeta::read(run_dir.join(format!("{version}.json")))
+            .is_ok_and(|meta| remote_meta.refs_eq(meta.commit_hashes))
+}
+
+/// Reads the `libraries.list` of a jar, or nothing if it is not a bundler.
+fn bundled_libraries(jar: &Path) -> Result<Vec<BundlerEntry>> {
+    Ok(Bundler::open(jar)?
+        .map(|bundler| bundler.libraries().to_vec())
+        .unwrap_or_default())
+}
+
+/// Builds versions into a run directory, stopping at the first version that
+/// fails.
 pub async fn run(
     versions: Vec<String>,
     run_dir: PathBuf,
     force_build: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let using_env = [8, 16, 17, 21]
-        .iter()
-        .all(|ver| std::env::var(format!("JAVA_HOME_{ver}")).is_ok());
-
-    if using_env {
-        info!("Using environment variables for java home instead of configuration file.");
+) -> Result<()> {
+    let mut run = Run::start(run_dir, force_build).await?;
+    for version in versions {
+        if let Err(err) = run.build(&version).await {
+            return run.finish(Err(err)).await;
+        }
     }
 
-    let config_file = run_dir.parent().unwrap().join("config.toml");
-    if !fs::exists(&config_file)? {
-        fs::write(config_file, toml::to_string_pretty(&Config::default())?)?;
-        info!("Generated default config file.")
+    run.finish(Ok(())).await
+}
+
+/// A run building versions into a run directory. The work every build shares,
+/// such as downloading BuildTools, is done once when the run starts, and the
+/// index, the changelog and the sinks are updated once when it finishes.
+pub struct Run {
+    run_dir: PathBuf,
+    force_build: bool,
+    config: Config,
+    config_file: PathBuf,
+    using_env: bool,
+    setup: Setup,
+    built: HashSet<String>,
+    changes: Vec<VersionChange>,
+    notifier: Notifier,
+}
+
+/// What the builds of a run share.
+struct Setup {
+    signer: Option<Signer>,
+    sinks: sink::Sinks,
+    piston_versions: PistonVersionsResponse,
+    temp_dir: PathBuf,
+    buildtools_path: PathBuf,
+    buildtools: BuildToolsInfo,
+    offline: bool,
+}
+
+impl Setup {
+    async fn prepare(run_dir: &Path, config: &Config) -> Result<Self> {
+        let signer = config.signer()?;
+        let sinks = config.sinks(run_dir)?;
+
+        info!("Fetching Piston metadata...");
+        let piston_versions = fetch_piston_meta().await?;
+
+        info!("Downloading BuildTools...");
+        let offline = http::is_offline();
+        let temp_dir = match config.workspace_dir() {
+            Some(workspace_dir) => {
+                fs::create_dir_all(&workspace_dir).map_err(Error::io(&workspace_dir))?;
+                workspace_dir
+            }
+            None => {
+                if offline {
+                    warn!("Offline mode without a workspace_dir in the config; BuildTools has nothing to reuse.");
+                }
+                create_temp_dir("bin-patch-gen")?
+            }
+        };
+
+        let buildtools_path = temp_dir.join("BuildTools.jar");
+        download_buildtools(buildtools_path.clone(), &config.buildtools_integrity()).await?;
+        let buildtools = BuildToolsInfo::read(&buildtools_path)?;
+        info!(
+            "Downloaded BuildTools successfully! ({})",
+            buildtools.version.as_deref().unwrap_or(&buildtools.sha1)
+        );
+
+        if !run_dir.exists() {
+            fs::create_dir_all(run_dir).map_err(Error::io(run_dir))?;
+        }
+
+        Ok(Self {
+            signer,
+            sinks,
+            piston_versions,
+            temp_dir,
+            buildtools_path,
+            buildtools,
+            offline,
+        })
     }
+}
+
+impl Run {
+    /// Starts a run, generating a default [`config::CONFIG_FILE`] in the working
+    /// directory if there is none.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the config cannot be read, or the Piston metadata or BuildTools
+    /// cannot be downloaded. Webhooks are notified of the latter.
+    pub async fn start(run_dir: PathBuf, force_build: bool) -> Result<Self> {
+        let using_env = [8, 16, 17, 21]
+            .iter()
+            .all(|ver| std::env::var(format!("JAVA_HOME_{ver}")).is_ok());
+
+        if using_env {
+            info!("Using environment variables for java home instead of configuration file.");
+        }
 
-    let config = config::read_config("config.toml")?;
+        let config_file = PathBuf::from(config::CONFIG_FILE);
+        let config = config::read_or_create_config(&config_file)?;
+        let notifier = Notifier::new(config.webhooks.clone());
+        let setup = match Setup::prepare(&run_dir, &config).await {
+            Ok(setup) => setup,
+            Err(err) => {
+                notifier.finish(Some(&err)).await;
+                return Err(err);
+            }
+        };
 
-    info!("Releases found: {versions:?}");
+        Ok(Self {
+            run_dir,
+            force_build,
+            config,
+            config_file,
+            using_env,
+            setup,
+            built: HashSet::new(),
+            changes: vec![],
+            notifier,
+        })
+    }
 
-    info!("Downloading BuildTools...");
-    let temp_dir = create_temp_dir("bin-patch-gen")?;
+    /// Builds a version, unless its output is up to date, and uploads it to the
+    /// sinks. Webhooks are notified whether it was built or failed.
+    pub async fn build(&mut self, version: &str) -> Result<()> {
+        self.notifier.start(version);
+        let result = self.build_version(version.to_owned()).await;
+        self.report(result).await
+    }
 
-    let buildtools_path = temp_dir.join("BuildTools.jar");
-    download_buildtools(buildtools_path.clone()).await?;
-    info!("Downloaded BuildTools successfully!");
+    /// Builds a version like [`Run::build`], but cancels the build and fails
+    /// the version if it takes longer than `timeout`.
+    pub async fn build_within(&mut self, version: &str, timeout: Duration) -> Result<()> {
+        self.notifier.start(version);
+        let result = tokio::time::timeout(timeout, self.build_version(version.to_owned()))
+            .await
+            .unwrap_or_else(|_| {
+                Err(Error::build_tools(version, format!("timed out after {}s", timeout.as_secs())))
+            });
+        self.report(result).await
+    }
 
-    let vanilla_jar_regex = Regex::new(VANILLA_JAR_REGEX)?;
-    let spigot_jar_regex = Regex::new(SPIGOT_SERVER_JAR_REGEX)?;
+    /// Reports the outcome of a build to the webhooks if it failed.
+    async fn report(&mut self, result: Result<()>) -> Result<()> {
+        if let Err(err) = &result {
+            self.notifier.failed(err).await;
+        }
 
-    if !run_dir.exists() {
-        fs::create_dir_all(&run_dir)?;
+        result
     }
 
-    for version in versions {
+    async fn build_version(&mut self, version: String) -> Result<()> {
+        let Setup {
+            signer,
+            sinks,
+            piston_versions,
+            temp_dir,
+            buildtools_path,
+            buildtools,
+            offline,
+        } = &self.setup;
+        let run_dir = &self.run_dir;
+        let config = &self.config;
+
         info!("Building Spigot for version {}...", version);
         let version_path = temp_dir.join(Path::new(&*version));
         let work_path = version_path.join(Path::new("work"));
-        let vanilla_jar_regex = vanilla_jar_regex.clone();
-        let spigot_jar_regex = spigot_jar_regex.clone();
         let library_file = &run_dir.join(format!("{version}.libs"));
+        let libraries_file = &run_dir.join(format!("{version}.libraries.json"));
 
-        let mc_version = MinecraftVersion::of(version.clone());
-        let java_home = if !using_env {
-            config.java_home(mc_version.get_java_version())
+        let mc_version = MinecraftVersion::of(version.clone())?;
+        let java_version = mc_version.get_java_version();
+        let java_home = if !self.using_env {
+            config.java_home(java_version)?
         } else {
-            PathBuf::from(
-                std::env::var(format!("JAVA_HOME_{}", mc_version.get_java_version())).unwrap(),
-            )
+            std::env::var(format!("JAVA_HOME_{java_version}"))
+                .map(PathBuf::from)
+                .map_err(|e| Error::config(&self.config_file, format!("JAVA_HOME_{java_version}: {e}")))?
         };
 
         let remote_meta = fetch_spigot_version_meta(version.clone()).await?;
 
+        if !self.force_build && is_up_to_date(run_dir, &version, &remote_meta) {
+            info!("Already built version {version}, skipping");
+            self.notifier.skip();
+            return Ok(());
+        }
+
         let version_file = &run_dir.join(format!("{version}.json"));
+        let mut previous_refs = None;
         if version_file.exists() {
-            let patched_meta = PatchedVersionMeta::read(version_file);
-
-            if patched_meta.is_err() {
-                warn!("{version} metadata is invalid or could not be read! Rebuilding...");
-            } else {
-                let patched_meta = patched_meta.unwrap();
-                if remote_meta.refs_eq(patched_meta.commit_hashes) && !force_build && library_file.exists() {
-                    info!("Already built version {version}, skipping");
-                    continue;
-                }
+            match PatchedVersionMeta::read(version_file) {
+                Ok(patched_meta) => previous_refs = Some(patched_meta.commit_hashes),
+                Err(err) => warn!("{version} metadata is invalid or could not be read ({err})! Rebuilding..."),
             }
         }
 
-        let result = run_buildtools(
+        let java = JavaInstallation::inspect(&java_home);
+        info!(
+            "Using Java {} ({})",
+            java.version.as_deref().unwrap_or("of unknown version"),
+            java.vendor.as_deref().unwrap_or("unknown vendor")
+        );
+        let mut provenance = Provenance::start(buildtools.clone(), java);
+
+
+        let stage = Instant::now();
+        let buildtools_run = run_buildtools(
             java_home,
             buildtools_path.clone(),
             version_path.clone(),
             &version,
+            *offline,
         )
         .await?;
-        let vanilla_jar = find_file(&vanilla_jar_regex, work_path).await?;
+        provenance.buildtools_args = buildtools_run.args;
+        provenance.stage("buildtools", stage);
+        let result = buildtools_run.spigot_jar;
+
+        let stage = Instant::now();
+        let vanilla_bundle = find_file(&VANILLA_JAR_PATTERN, work_path).await?;
+        let piston_server = check_vanilla_jar(
+            piston_versions,
+            &version,
+            &sha1(&vanilla_bundle)?,
+            config.strict_vanilla_check,
+        )
+        .await?;
+        provenance.stage("vanilla_check", stage);
 
         info!(
             "BuildTools finished building Spigot for version {}!",
             version
         );
-        info!("Built jar location: {}", result.to_str().unwrap());
-
-        info!("Checking whether jars need extraction...");
-        let needs_extraction_vanilla = jar::has_dir(&vanilla_jar, JAR_VERSIONS_PATH).unwrap();
-        let needs_extraction_spigot = jar::has_dir(&result, JAR_VERSIONS_PATH).unwrap();
-
-        let vanilla_jar = if needs_extraction_vanilla {
-            info!("Vanilla jar needs extraction");
-            info!("Extracting vanilla jar...");
-            let vanilla_jar_extraction_path = version_path.join(Path::new("vanilla_jar"));
-            prepare_extraction_path(&vanilla_jar_extraction_path)
-                .await
-                .unwrap();
-            extract_jar(&vanilla_jar, &vanilla_jar_extraction_path).unwrap();
-            info!("Successfully extracted vanilla jar!");
-
-            let extraction_path = vanilla_jar_extraction_path.join(Path::new(JAR_VERSIONS_PATH));
-            let versions_file_path = vanilla_jar_extraction_path
-                .join("META-INF")
-                .join("versions.list");
-            let file_content = fs::read_to_string(&versions_file_path);
-
-            if file_content.is_err() {
-                warn!("Failed to read versions.list. Will use {vanilla_jar:?} instead.");
-                vanilla_jar
-            } else {
-                let file_content = file_content.unwrap();
-                let split_content = file_content.split("\t").collect::<Vec<&str>>();
-
-                let jar_path_relative = split_content.get(2).unwrap();
-
-                extraction_path.join(jar_path_relative)
-            }
-        } else {
-            info!("Vanilla jar does not need extraction");
-            vanilla_jar
-        };
+        info!("Built jar location: {}", result.display());
+
+        let stage = Instant::now();
+        let vanilla_jar_extraction_path = version_path.join("vanilla_jar");
+        prepare_extraction_path(&vanilla_jar_extraction_path).await?;
+        let vanilla_jar = unbundle(&vanilla_bundle, &vanilla_jar_extraction_path)
+            .map_err(Error::in_version(&version))?;
+        info!("Vanilla server jar: {}", vanilla_jar.display());
+
+        let spigot_jar_extraction_path = version_path.join("spigot_jar");
+        prepare_extraction_path(&spigot_jar_extraction_path).await?;
+        let spigot_jar = unbundle(&result, &spigot_jar_extraction_path)
+            .map_err(Error::in_version(&version))?;
+        info!("Spigot server jar: {}", spigot_jar.display());
+        provenance.stage("unbundle", stage);
 
-        let spigot_jar = if needs_extraction_spigot {
-            info!("Spigot jar needs extraction");
-            info!("Extracting spigot jar...");
-            let spigot_jar_extraction_path = version_path.join(Path::new("spigot_jar"));
-            prepare_extraction_path(&spigot_jar_extraction_path)
-                .await
-                .unwrap();
-            extract_jar(&result, &spigot_jar_extraction_path).unwrap();
-            info!("Successfully extracted spigot jar!");
-
-            let file = find_file(
-                &spigot_jar_regex,
-                spigot_jar_extraction_path.join(Path::new(JAR_VERSIONS_PATH)),
-            )
-            .await;
-            if let Ok(file) = file {
-                file
-            } else {
-                warn!("Failed to read versions.list. Will use {result:?} instead.");
-                result
-            }
+        let patch_file = &run_dir.join(format!("{version}.patch"));
+
+        let change = VersionChange::new(&version, previous_refs.as_ref(), &remote_meta.refs);
+        let archived = if change.kind == ChangeKind::Updated && config.history_retention != Some(0) {
+            history::archive(run_dir, &version)?
         } else {
-            info!("Spigot jar does not need extraction");
-            result
+            None
         };
 
-        let patch_file = &run_dir.join(format!("{version}.patch"));
-
         info!("Generating diff...");
+        let stage = Instant::now();
         write_patch(&vanilla_jar, &spigot_jar, patch_file)?;
+        provenance.stage("diff", stage);
         info!("Diff generated!");
 
-        let vanilla_jar_hash = sha1(vanilla_jar)?;
+        let vanilla_jar_hash = sha1(&vanilla_jar)?;
         
         info!("Reading BuildData...");
         let build_data_info = version_path.join("BuildData/info.json");
         let fallback_vanilla_download_url = format!("{PISTON_DATA_BASE_URL}/{vanilla_jar_hash}/server.jar");
         
-        let vanilla_download_url = if build_data_info.exists() {
-            match serde_json::from_str::<SpigotBuildData>(&fs::read_to_string(build_data_info)?) {
+        let vanilla_download_url = if let Some(server) = &piston_server {
+            server.url.clone()
+        } else if build_data_info.exists() {
+            let build_data = fs::read_to_string(&build_data_info).map_err(Error::io(&build_data_info))?;
+            match serde_json::from_str::<SpigotBuildData>(&build_data) {
                 Ok(data) => data.server_url,
                 Err(_) => fallback_vanilla_download_url
             }
@@ -326,50 +512,249 @@ pub async fn run(
         };
         info!("Read BuildData!");
 
+        let stage = Instant::now();
+        let server_pom = version_path.join("Spigot/Spigot-Server/pom.xml");
+        let maven_repository = config.maven_repository();
+        let (server_pom, direct_libraries) = maven::read_dependencies(server_pom, maven_repository.as_deref())
+            .map_err(Error::in_version(&version))?;
+        let pom_libraries = maven::resolve(&server_pom, maven_repository.as_deref())
+            .map_err(Error::in_version(&version))?;
+        let (pom_libraries, library_rules) = maven::rules::apply(
+            &config.library_rules,
+            &version,
+            pom_libraries,
+            maven_repository.as_deref(),
+            &server_pom.repositories,
+        )
+        .map_err(Error::in_version(&version))?;
+        let spigot_bundled = bundled_libraries(&result).map_err(Error::in_version(&version))?;
+        let pom_libraries = EmbeddedArtifacts::scan(&spigot_jar, &spigot_bundled)
+            .and_then(|embedded| embedded.mark(pom_libraries, maven_repository.as_deref()))
+            .map_err(Error::in_version(&version))?;
+        provenance.stage("libraries", stage);
+        provenance.finish();
+
         let patched_meta = PatchedVersionMeta {
-            patch_file: patch_file
-                .file_name()
-                .unwrap()
-                .to_string_lossy()
-                .into_owned(),
+            schema_version: config::METADATA_SCHEMA_VERSION,
+            patch_file: format!("{version}.patch"),
             commit_hashes: remote_meta.refs,
             patch_hash: sha1(patch_file)?,
             vanilla_jar_hash,
-            patched_jar_hash: sha1(spigot_jar)?,
+            patched_jar_hash: sha1(&spigot_jar)?,
             vanilla_download_url,
+            vanilla_download_hash: piston_server.as_ref().map(|server| server.sha1.clone()),
+            vanilla_download_size: piston_server.as_ref().map(|server| server.size),
+            library_rules,
+            provenance: Some(provenance),
+            patch_checksums: Some(Checksums::of(patch_file)?),
+            vanilla_jar_checksums: Some(Checksums::of(&vanilla_jar)?),
+            patched_jar_checksums: Some(Checksums::of(spigot_jar)?),
         };
 
-        patched_meta.write(version_file)?;
+        patched_meta.write(version_file).map_err(Error::in_version(&version))?;
+        if let Some(signer) = signer {
+            signer.sign_file(version_file)?;
+        }
         info!("Wrote version metadata file!");
-        
-        let server_pom = version_path.join("Spigot/Spigot-Server/pom.xml");
-        
-        let (project, maven_dependencies) = maven::read_dependencies(server_pom)?;
-        
-        MavenDependency::write(project, library_file, maven_dependencies)?;
+
+        MavenDependency::write(library_file, &direct_libraries).map_err(Error::in_version(&version))?;
+
+        let libraries = libraries::merge(
+            &bundled_libraries(&vanilla_bundle).map_err(Error::in_version(&version))?,
+            &spigot_bundled,
+            &pom_libraries,
+        );
+        LibrariesManifest::new(&version, libraries)
+            .write(libraries_file)
+            .map_err(Error::in_version(&version))?;
+        info!("Wrote library files!");
+
+        if !sinks.is_empty() {
+            verify_build(run_dir, &version, &vanilla_jar, &version_path, signer.as_ref()).await?;
+            let files = sink::version_files(run_dir, &version, archived.as_deref())?;
+            sinks.upload(run_dir, &files).await?;
+        }
+
+        self.notifier.built(&change, &patched_meta).await;
+        self.built.insert(version);
+        self.changes.push(change);
+
+        Ok(())
+    }
+
+    /// Finishes the run. Unless it failed, the history is pruned, and the
+    /// index and the changelog are updated and uploaded to the sinks. Webhooks
+    /// are sent the summary.
+    ///
+    /// # Arguments
+    ///
+    /// * `result` - The outcome of the builds.
+    pub async fn finish(mut self, result: Result<()>) -> Result<()> {
+        let result = match result {
+            Ok(()) => self.finalize().await,
+            Err(err) => Err(err),
+        };
+        self.notifier.finish(result.as_ref().err()).await;
+
+        result
+    }
+
+    async fn finalize(&mut self) -> Result<()> {
+        let run_dir = &self.run_dir;
+        let Setup { signer, sinks, .. } = &self.setup;
+
+        let pruned = match self.config.history_retention {
+            Some(keep) => history::prune(run_dir, keep)?,
+            None => vec![],
+        };
+        let index = index::finalize(run_dir, &self.built, signer.as_ref())?;
+        let changelog = Changelog::new(std::mem::take(&mut self.changes));
+        // A run without changes keeps the changelog of the last run that had some.
+        if !changelog.versions.is_empty() {
+            changelog.write(run_dir)?;
+        }
+        info!("{}", changelog.subject());
+        info!("Updated index with {} versions", index.versions.len());
+
+        if !sinks.is_empty() {
+            sinks.upload(run_dir, &sink::run_files(run_dir)).await?;
+            sinks.remove(run_dir, &pruned).await?;
+            sinks.finish(run_dir).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks a version before it is uploaded: its patch must turn the vanilla jar
+/// into the patched jar of its metadata, and the metadata must be signed if a
+/// signer is configured.
+///
+/// # Arguments
+///
+/// * `run_dir` - The run directory.
+/// * `version` - The version.
+/// * `vanilla_jar` - The vanilla jar BuildTools used.
+/// * `work_dir` - A directory the patched jar is written to.
+/// * `signer` - The signer, if any.
+async fn verify_build(
+    run_dir: &Path,
+    version: &str,
+    vanilla_jar: &Path,
+    work_dir: &Path,
+    signer: Option<&Signer>,
+) -> Result<()> {
+    let metadata = run_dir.join(format!("{version}.json"));
+    let patch_file = run_dir.join(format!("{version}.patch"));
+    let patched_jar = work_dir.join("verify.jar");
+    let public_key = signer.map(Signer::public_key);
+
+    patch_checked(vanilla_jar, &patched_jar, &patch_file, &metadata, public_key.as_ref()).await?;
+
+    info!("Verified the patch of {version}");
+    fs::remove_file(&patched_jar).map_err(Error::io(&patched_jar))
+}
+
+/// Applies a patch after checking it against its signed metadata.
+///
+/// The signature of `metadata` and the hashes of the patch and `old` are
+/// checked before anything is written, the hash of `new` after patching.
+///
+/// # Arguments
+///
+/// * `old` - The vanilla server jar.
+/// * `new` - The path the patched jar is written to.
+/// * `patch` - The patch.
+/// * `metadata` - The `{version}.json` of the patch.
+/// * `public_key` - The key the metadata must be signed with.
+pub async fn patch_verified<P: AsRef<Path>>(
+    old: P,
+    new: P,
+    patch: P,
+    metadata: P,
+    public_key: &signing::VerifyingKey,
+) -> Result<()> {
+    let meta = signing::verify_patch(public_key, metadata, &patch)?;
+    let old_hash = sha1(&old)?;
+    if !old_hash.eq_ignore_ascii_case(&meta.vanilla_jar_hash) {
+        return Err(Error::signature(
+            &old,
+            format!("has sha1 {old_hash}, but the patch applies to {}", meta.vanilla_jar_hash),
+        ));
+    }
+
+    self::patch(old.as_ref(), new.as_ref(), patch.as_ref()).await?;
+
+    let new_hash = sha1(&new)?;
+    if !new_hash.eq_ignore_ascii_case(&meta.patched_jar_hash) {
+        return Err(Error::signature(
+            &new,
+            format!("has sha1 {new_hash}, but the signed hash is {}", meta.patched_jar_hash),
+        ));
     }
 
     Ok(())
 }
 
-pub async fn patch<P: AsRef<Path>>(old: P, new: P, patch: P) -> io::Result<()> {
-    let mut patch_file = File::open(patch)?;
-    let mut patch_buf = vec![];
-    patch_file.read_to_end(&mut patch_buf)?;
+/// Applies a patch and checks the result against its metadata. With a public
+/// key, the metadata must be signed with it, as with [`patch_verified`];
+/// otherwise only the hashes of `old` and `new` are checked.
+///
+/// # Arguments
+///
+/// * `old` - The vanilla server jar.
+/// * `new` - The path the patched jar is written to.
+/// * `patch` - The patch.
+/// * `metadata` - The `{version}.json` of the patch.
+/// * `public_key` - The key the metadata must be signed with, if any.
+pub async fn patch_checked<P: AsRef<Path>>(
+    old: P,
+    new: P,
+    patch: P,
+    metadata: P,
+    public_key: Option<&signing::VerifyingKey>,
+) -> Result<()> {
+    if let Some(public_key) = public_key {
+        return patch_verified(old, new, patch, metadata, public_key).await;
+    }
 
-    info!("Patching...");
+    let meta = PatchedVersionMeta::read(&metadata)?;
+    let old_hash = sha1(&old)?;
+    if !old_hash.eq_ignore_ascii_case(&meta.vanilla_jar_hash) {
+        return Err(Error::verification(
+            &old,
+            format!("has sha1 {old_hash}, but the patch applies to {}", meta.vanilla_jar_hash),
+        ));
+    }
+
+    self::patch(old.as_ref(), new.as_ref(), patch.as_ref()).await?;
 
-    let mut old_file = File::open(old)?;
-    let mut old_buf = vec![];
-    old_file.read_to_end(&mut old_buf)?;
+    let new_hash = sha1(&new)?;
+    if !new_hash.eq_ignore_ascii_case(&meta.patched_jar_hash) {
+        return Err(Error::verification(
+            &new,
+            format!("has sha1 {new_hash}, but the metadata lists {}", meta.patched_jar_hash),
+        ));
+    }
+
+    Ok(())
+}
+
+pub async fn patch<P: AsRef<Path>>(old: P, new: P, patch: P) -> Result<()> {
+    let patch = patch.as_ref();
+    let patch_buf = fs::read(patch).map_err(Error::io(patch))?;
+
+    info!("Patching...");
 
-    let mut new_file = File::create(new)?;
+    let old_buf = fs::read(&old).map_err(Error::io(&old))?;
     let mut new_buf = vec![];
 
-    let patcher = Bspatch::new(&patch_buf)?;
-    patcher.apply(&old_buf, &mut new_buf)?;
+    let patcher = Bspatch::new(&patch_buf).map_err(|e| Error::patch(patch, e))?;
+    patcher
+        .apply(&old_buf, &mut new_buf)
+        .map_err(|e| Error::patch(patch, e))?;
 
-    new_file.write_all(&new_buf)?;
+    fs::write(&new, &new_buf).map_err(Error::io(&new))?;
 
     info!("Patched!");
 
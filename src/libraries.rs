@@ -0,0 +1,226 @@
+//! The libraries a patched server needs on its classpath.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use proc_macros::serial_snake;
+
+use crate::error::{Error, Result};
+use crate::jar::bundler::BundlerEntry;
+use crate::jar::embedded::Embedding;
+use crate::maven::MavenDependency;
+
+/// The repository hosting the libraries of vanilla Minecraft.
+pub const MOJANG_LIBRARIES_URL: &str = "https://libraries.minecraft.net/";
+
+/// The Maven Central repository.
+pub const MAVEN_CENTRAL_URL: &str = "https://repo.maven.apache.org/maven2/";
+
+/// The version of the [`LibrariesManifest`] format. It is increased whenever
+/// the format changes in a way older consumers cannot read.
+pub const LIBRARIES_SCHEMA_VERSION: u32 = 1;
+
+/// The libraries file written for every version, `{version}.libraries.json`.
+#[serial_snake]
+pub struct LibrariesManifest {
+    pub schema_version: u32,
+    pub minecraft_version: String,
+    pub libraries: Vec<Library>,
+}
+
+/// A checksum of a library jar.
+#[serial_snake]
+pub struct Checksum {
+    /// The hash algorithm, for example `sha256`.
+    pub algorithm: String,
+    /// The lowercase hex encoded hash.
+    pub value: String,
+}
+
+/// A library and where to download it from.
+#[serial_snake]
+pub struct Library {
+    pub group_id: String,
+    pub artifact_id: String,
+    pub version: String,
+    pub classifier: Option<String>,
+    /// The packaging type, usually `jar`.
+    #[serde(rename = "type")]
+    pub packaging: String,
+    /// The Maven scope, `compile` or `runtime`.
+    pub scope: String,
+    /// The base URL of the repository hosting the jar.
+    pub repository: String,
+    /// The path of the jar relative to `repository`.
+    pub path: String,
+    pub checksum: Option<Checksum>,
+    /// How the library is embedded in the patched jar, if it is. Such libraries
+    /// must not be put on the classpath again.
+    pub embedded: Option<Embedding>,
+}
+
+impl Library {
+    /// Creates a library from a line of a bundler's `libraries.list`.
+    ///
+    /// # Returns
+    ///
+    /// The library, or `None` if the id is not a Maven coordinate.
+    pub fn from_bundler(entry: &BundlerEntry, repository: &str) -> Option<Self> {
+        let parts = entry.id.split(':').collect::<Vec<_>>();
+        let (group_id, artifact_id, version, classifier) = match parts[..] {
+            [group, artifact, version] => (group, artifact, version, None),
+            [group, artifact, version, classifier] => {
+                (group, artifact, version, Some(classifier.to_owned()))
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            group_id: group_id.to_owned(),
+            artifact_id: artifact_id.to_owned(),
+            version: version.to_owned(),
+            classifier,
+            packaging: "jar".to_owned(),
+            scope: "runtime".to_owned(),
+            repository: repository.to_owned(),
+            path: entry.path.clone(),
+            checksum: Some(Checksum {
+                algorithm: "sha256".to_owned(),
+                value: entry.hash.clone(),
+            }),
+            embedded: None,
+        })
+    }
+
+    /// Creates a library from a POM dependency without a known checksum.
+    ///
+    /// # Arguments
+    ///
+    /// * `dependency` - The dependency.
+    /// * `repository` - The repository used if the hosting repository of the
+    ///   dependency is unknown.
+    pub fn from_dependency(dependency: &MavenDependency, repository: &str) -> Self {
+        let repository = dependency.repository.as_deref().unwrap_or(repository);
+        let mut library = Self {
+            group_id: dependency.group_id.clone(),
+            artifact_id: dependency.artifact_id.clone(),
+            version: dependency.version.clone(),
+            classifier: dependency.classifier.clone(),
+            packaging: dependency.packaging.clone().unwrap_or_else(|| "jar".to_owned()),
+            scope: dependency.scope.clone().unwrap_or_else(|| "compile".to_owned()),
+            repository: repository.to_owned(),
+            path: String::new(),
+            checksum: None,
+            embedded: dependency.embedded,
+        };
+        library.path = library.repository_path();
+        library
+    }
+
+    /// The path of the artifact inside a Maven repository.
+    pub fn repository_path(&self) -> String {
+        let classifier = self
+            .classifier
+            .as_ref()
+            .map(|classifier| format!("-{classifier}"))
+            .unwrap_or_default();
+
+        format!(
+            "{}/{artifact}/{version}/{artifact}-{version}{classifier}.{}",
+            self.group_id.replace('.', "/"),
+            self.packaging,
+            artifact = self.artifact_id,
+            version = self.version,
+        )
+    }
+
+    /// The `group:artifact[:classifier]` part of the coordinates, identifying
+    /// the library regardless of its version.
+    pub fn key(&self) -> String {
+        match &self.classifier {
+            Some(classifier) => format!("{}:{}:{classifier}", self.group_id, self.artifact_id),
+            None => format!("{}:{}", self.group_id, self.artifact_id),
+        }
+    }
+}
+
+/// Merges the libraries bundled into the vanilla and Spigot jars with the
+/// dependencies declared in Spigot's POM.
+///
+/// Bundled libraries take precedence, as they are exactly what the server ships
+/// with and come with checksums. Libraries vanilla bundles are attributed to
+/// Mojang's repository, everything else to the repository hosting it or, if
+/// that is unknown, Maven Central.
+///
+/// # Arguments
+///
+/// * `vanilla` - The `libraries.list` entries of the vanilla bundler.
+/// * `spigot` - The `libraries.list` entries of the Spigot bundler.
+/// * `pom` - The resolved POM dependencies.
+pub fn merge(
+    vanilla: &[BundlerEntry],
+    spigot: &[BundlerEntry],
+    pom: &[MavenDependency],
+) -> Vec<Library> {
+    let vanilla_ids = vanilla
+        .iter()
+        .map(|entry| entry.id.as_str())
+        .collect::<HashSet<_>>();
+    let mut seen = HashSet::new();
+    let mut libraries = vec![];
+
+    let declared = pom
+        .iter()
+        .map(|dependency| Library::from_dependency(dependency, MAVEN_CENTRAL_URL))
+        .collect::<Vec<_>>();
+    let hosted = pom
+        .iter()
+        .zip(&declared)
+        .filter(|(dependency, _)| dependency.repository.is_some())
+        .map(|(_, library)| (library.key(), library.repository.as_str()))
+        .collect::<HashMap<_, _>>();
+
+    let bundled = spigot.iter().chain(vanilla).filter_map(|entry| {
+        let mut library = Library::from_bundler(entry, MAVEN_CENTRAL_URL)?;
+        if vanilla_ids.contains(entry.id.as_str()) {
+            library.repository = MOJANG_LIBRARIES_URL.to_owned();
+        } else if let Some(repository) = hosted.get(&library.key()) {
+            library.repository = (*repository).to_owned();
+        }
+        Some(library)
+    });
+
+    for library in bundled.collect::<Vec<_>>().into_iter().chain(declared) {
+        if seen.insert(library.key()) {
+            libraries.push(library);
+        }
+    }
+
+    libraries
+}
+
+impl LibrariesManifest {
+    pub fn new<V: Into<String>>(minecraft_version: V, libraries: Vec<Library>) -> Self {
+        Self {
+            schema_version: LIBRARIES_SCHEMA_VERSION,
+            minecraft_version: minecraft_version.into(),
+            libraries,
+        }
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(Error::io(path))?;
+
+        serde_json::from_str(&content).map_err(Error::metadata(path.display().to_string()))
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let json = serde_json::to_string_pretty(self)
+            .map_err(Error::metadata(path.display().to_string()))?;
+
+        fs::write(path, json).map_err(Error::io(path))
+    }
+}
@@ -1,10 +1,15 @@
-use bin_patch_gen::run;
+use bin_patch_gen::daemon::{self, Daemon};
+use bin_patch_gen::error::Error;
+use bin_patch_gen::{config, http, migrate, publish, run, server, signing};
 use bin_patch_gen::util::TimeFormatter;
 use bin_patch_gen::version::fetch_versions;
-use clap::{command, Parser, Subcommand};
+use clap::{Parser, Subcommand};
 use std::env::current_dir;
 use std::fs;
+use std::io::ErrorKind;
 use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::TcpListener;
 use tracing::info;
 use tracing_subscriber::fmt::format;
 
@@ -24,6 +29,11 @@ struct Cli {
     #[arg(short = 'f', long = "force", value_name = "force")]
     pub force_build: bool,
 
+    /// Whether to work without network access, serving every download from the
+    /// HTTP cache and reusing the persistent BuildTools workspace.
+    #[arg(long, value_name = "offline")]
+    pub offline: bool,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -38,9 +48,88 @@ enum Commands {
         new: PathBuf,
         /// The bsdiff patch file, compressed with bzip2.
         patch: PathBuf,
+        /// A public key the metadata of the patch must be signed with. The patch
+        /// is only applied if the signature and all hashes match.
+        #[arg(long, value_name = "public_key")]
+        public_key: Option<PathBuf>,
+        /// The `{version}.json` metadata of the patch. Defaults to the patch path
+        /// with a `json` extension.
+        #[arg(long, value_name = "metadata", requires = "public_key")]
+        metadata: Option<PathBuf>,
+    },
+    /// Verifies the signatures of the index and all metadata, and the hashes of
+    /// all files they list.
+    Verify {
+        /// The public key the output must be signed with.
+        #[arg(long, value_name = "public_key")]
+        public_key: PathBuf,
+        /// The directory to verify, such as a checkout of the patches repository.
+        /// Defaults to the run directory.
+        directory: Option<PathBuf>,
+    },
+    /// Verifies the run directory, commits the changed output to git and pushes it
+    /// to the configured remote. Nothing is committed if verification fails.
+    Publish {
+        /// The public key the output must be signed with. Defaults to the public key
+        /// of the configured signing key; without either, only hashes are verified.
+        #[arg(long, value_name = "public_key")]
+        public_key: Option<PathBuf>,
+        /// Only commits, without pushing.
+        #[arg(long, value_name = "no_push")]
+        no_push: bool,
+        /// The directory to publish. Defaults to the run directory.
+        directory: Option<PathBuf>,
+    },
+    /// Polls Spigot periodically and builds every version that is missing or was
+    /// built from other refs, serving a health check on `/health`.
+    Daemon {
+        /// How many seconds to wait between polls. Defaults to the configured
+        /// `daemon_interval`, or an hour.
+        #[arg(long, value_name = "interval")]
+        interval: Option<u64>,
+        /// The address to serve the health check on. Defaults to the configured
+        /// `daemon_health_address`.
+        #[arg(long, value_name = "health_address")]
+        health_address: Option<String>,
+        /// Polls and builds only once, then exits.
+        #[arg(long, value_name = "once")]
+        once: bool,
+    },
+    /// Serves the output over HTTP: `/index.json`, `/{version}.json`,
+    /// `/{version}.patch`, `/{version}.libs` and the other output files.
+    Serve {
+        /// The address to listen on. Defaults to the configured `serve_address`.
+        #[arg(long, value_name = "address")]
+        address: Option<String>,
+        /// Also serves patched server jars on `/{version}/server.jar`, patching
+        /// them on the first request and caching them.
+        #[arg(long, value_name = "patched_jars")]
+        patched_jars: bool,
+        /// The public key the metadata must be signed with before a jar is patched.
+        /// Defaults to the public key of the configured signing key.
+        #[arg(long, value_name = "public_key")]
+        public_key: Option<PathBuf>,
+        /// The directory to serve. Defaults to the run directory.
+        directory: Option<PathBuf>,
+    },
+    /// Upgrades the metadata of every version to the current schema and regenerates
+    /// the index, without rebuilding anything.
+    Migrate {
+        /// The directory containing the metadata, such as a checkout of the patches
+        /// repository. Defaults to the run directory.
+        directory: Option<PathBuf>,
     },
 }
 
+fn run_dir() -> std::io::Result<PathBuf> {
+    let current_dir = current_dir()?;
+    if current_dir.ends_with("run") {
+        Ok(current_dir)
+    } else {
+        Ok(current_dir.join("run"))
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let fmt = format()
@@ -52,23 +141,111 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let cli = Cli::parse();
 
-    if let Some(Commands::Patch { old, new, patch }) = cli.command {
-        bin_patch_gen::patch(old, new, patch).await?;
+    // Patching and verifying work without a config and a run writes the default
+    // one, everything else needs one.
+    let config = match config::read_config(config::CONFIG_FILE) {
+        Ok(config) => config,
+        Err(Error::Io { source, .. })
+            if source.kind() == ErrorKind::NotFound
+                && matches!(cli.command, None | Some(Commands::Patch { .. } | Commands::Verify { .. })) =>
+        {
+            config::Config::default()
+        }
+        Err(err) => return Err(err.into()),
+    };
+    if let Some(user_agent) = &config.user_agent {
+        http::set_user_agent(user_agent.clone());
+    }
+    http::set_cache_dir(config.cache_dir());
+    http::set_offline(cli.offline);
+
+    match cli.command {
+        Some(Commands::Patch { old, new, patch, public_key: None, .. }) => {
+            bin_patch_gen::patch(old, new, patch).await?;
+
+            return Ok(());
+        }
+        Some(Commands::Patch { old, new, patch, public_key: Some(public_key), metadata }) => {
+            let public_key = signing::read_public_key(public_key)?;
+            let metadata = metadata.unwrap_or_else(|| patch.with_extension("json"));
+            bin_patch_gen::patch_verified(old, new, patch, metadata, &public_key).await?;
+
+            return Ok(());
+        }
+        Some(Commands::Verify { public_key, directory }) => {
+            let public_key = signing::read_public_key(public_key)?;
+            let directory = directory.map_or_else(run_dir, Ok)?;
+            let index = signing::verify_run_dir(&public_key, &directory)?;
+            info!("Verified {} versions", index.versions.len());
+
+            return Ok(());
+        }
+        Some(Commands::Publish { public_key, no_push, directory }) => {
+            let mut options = config.publish_options()?;
+            if let Some(public_key) = public_key {
+                options.public_key = Some(signing::read_public_key(public_key)?);
+            }
+            if no_push {
+                options.remote = None;
+            }
+            let directory = directory.map_or_else(run_dir, Ok)?;
+            let report = publish::publish(&directory, &options)?;
+            info!(
+                "Published {} changed files{}",
+                report.files.len(),
+                if report.pushed { "" } else { " without pushing" }
+            );
+
+            return Ok(());
+        }
+        Some(Commands::Daemon { interval, health_address, once }) => {
+            let interval = interval.map_or_else(|| config.daemon_interval(), Duration::from_secs);
+            let state_file = config.cache_dir().join(daemon::STATE_FILE);
+            let daemon = Daemon::new(run_dir()?, state_file, interval)?;
+
+            let health_address = health_address.as_deref().unwrap_or(config.daemon_health_address());
+            let listener = TcpListener::bind(health_address).await?;
+            tokio::spawn(daemon.clone().serve_health(listener));
 
-        return Ok(());
+            daemon.run(once).await?;
+
+            return Ok(());
+        }
+        Some(Commands::Serve { address, patched_jars, public_key, directory }) => {
+            let mut options = config.serve_options()?;
+            options.patched_jars |= patched_jars;
+            if let Some(public_key) = public_key {
+                options.public_key = Some(signing::read_public_key(public_key)?);
+            }
+            let directory = directory.map_or_else(run_dir, Ok)?;
+
+            let address = address.as_deref().unwrap_or(config.serve_address());
+            let listener = TcpListener::bind(address).await?;
+            server::serve(listener, directory, options).await?;
+
+            return Ok(());
+        }
+        Some(Commands::Migrate { directory }) => {
+            let directory = directory.map_or_else(run_dir, Ok)?;
+            let report = migrate::migrate_run_dir(&directory, config.signer()?.as_ref())?;
+            info!(
+                "Migrated {} versions, {} were up to date",
+                report.migrated.len(),
+                report.current.len()
+            );
+
+            return Ok(());
+        }
+        None => {}
     }
 
     let versions = if let Some(version) = cli.version {
         vec![version]
     } else {
-        fetch_versions().await
+        fetch_versions().await?
     };
 
-    let run_dir = if current_dir()?.ends_with("run") {
-        current_dir()?
-    } else {
-        current_dir()?.join("run")
-    };
+    let run_dir = run_dir()?;
 
     if cli.clean {
         info!("Cleaning run directory");
@@ -76,5 +253,5 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         fs::create_dir_all(&run_dir)?;
     }
 
-    run(versions, run_dir, cli.force_build).await
+    Ok(run(versions, run_dir, cli.force_build).await?)
 }
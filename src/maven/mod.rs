@@ -0,0 +1,260 @@
+//! Reads the dependencies Spigot declares in its POM.
+
+pub mod pom;
+pub mod resolver;
+pub mod rules;
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::path::PathBuf;
+use std::{env, fs, path::Path};
+
+use tracing::warn;
+
+use crate::error::{Error, Result};
+use crate::jar::embedded::Embedding;
+use crate::maven::pom::{EffectivePom, PomDependency, PomRepository};
+use crate::maven::resolver::Resolver;
+
+/// A dependency declared in a POM.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MavenDependency {
+    pub group_id: String,
+    pub artifact_id: String,
+    pub version: String,
+    pub classifier: Option<String>,
+    /// The packaging type, `jar` if unset.
+    pub packaging: Option<String>,
+    /// The Maven scope, `compile` if unset.
+    pub scope: Option<String>,
+    /// The URL of the repository hosting the dependency, if known.
+    pub repository: Option<String>,
+    /// How the dependency is embedded in the patched jar, if it is.
+    pub embedded: Option<Embedding>,
+}
+
+impl TryFrom<&PomDependency> for MavenDependency {
+    type Error = String;
+
+    fn try_from(value: &PomDependency) -> std::result::Result<Self, Self::Error> {
+        let version = value
+            .version
+            .as_ref()
+            .ok_or_else(|| format!("{}:{} has no version", value.group_id, value.artifact_id))?;
+        if version.contains("${") {
+            return Err(format!(
+                "{}:{} has an unresolved version {version}",
+                value.group_id, value.artifact_id
+            ));
+        }
+
+        Ok(MavenDependency {
+            group_id: value.group_id.clone(),
+            artifact_id: value.artifact_id.clone(),
+            version: version.clone(),
+            classifier: value.classifier.clone(),
+            packaging: value.packaging.clone(),
+            scope: value.scope.clone(),
+            repository: None,
+            embedded: None,
+        })
+    }
+}
+
+impl Display for MavenDependency {
+    /// Formats the dependency as `group:artifact:version[:classifier]`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.group_id, self.artifact_id, self.version)?;
+        if let Some(classifier) = &self.classifier {
+            write!(f, ":{classifier}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for MavenDependency {
+    type Err = String;
+
+    /// Parses `group:artifact:version[:classifier]` coordinates.
+    fn from_str(coordinates: &str) -> std::result::Result<Self, Self::Err> {
+        let parts = coordinates.trim().split(':').collect::<Vec<_>>();
+        let (group_id, artifact_id, version, classifier) = match parts[..] {
+            [group, artifact, version] => (group, artifact, version, None),
+            [group, artifact, version, classifier] => {
+                (group, artifact, version, Some(classifier.to_owned()))
+            }
+            _ => return Err(format!("invalid Maven coordinates {coordinates:?}")),
+        };
+        if [group_id, artifact_id, version].iter().any(|part| part.is_empty()) {
+            return Err(format!("invalid Maven coordinates {coordinates:?}"));
+        }
+
+        Ok(MavenDependency {
+            group_id: group_id.to_owned(),
+            artifact_id: artifact_id.to_owned(),
+            version: version.to_owned(),
+            classifier,
+            packaging: None,
+            scope: None,
+            repository: None,
+            embedded: None,
+        })
+    }
+}
+
+impl MavenDependency {
+    /// The file name of the dependency's artifact in a Maven repository.
+    pub fn file_name(&self) -> String {
+        let classifier = self
+            .classifier
+            .as_ref()
+            .map(|classifier| format!("-{classifier}"))
+            .unwrap_or_default();
+        let packaging = self.packaging.as_deref().unwrap_or("jar");
+
+        format!("{}-{}{classifier}.{packaging}", self.artifact_id, self.version)
+    }
+
+    /// Finds the repository the dependency was downloaded from, according to the
+    /// `_remote.repositories` file Maven writes next to every artifact in the
+    /// local repository.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_repository` - The local Maven repository.
+    /// * `repositories` - The repositories declared by the POM chain.
+    ///
+    /// # Returns
+    ///
+    /// The URL of the repository, or `None` if the dependency is not in the local
+    /// repository, was installed locally or came from an undeclared repository.
+    pub fn hosting_repository(
+        &self,
+        local_repository: &Path,
+        repositories: &[PomRepository],
+    ) -> Option<String> {
+        let path = local_repository
+            .join(self.group_id.replace('.', "/"))
+            .join(&self.artifact_id)
+            .join(&self.version)
+            .join("_remote.repositories");
+        let content = fs::read_to_string(path).ok()?;
+
+        // Lines look like `guava-32.1.2-jre.jar>central=`, an empty id meaning
+        // the artifact was installed locally.
+        let file_name = self.file_name();
+        let id = content
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.trim().trim_end_matches('=').split_once('>'))
+            .find(|(file, _)| *file == file_name)
+            .map(|(_, id)| id)?;
+        if id.is_empty() {
+            return None;
+        }
+
+        let repository = repositories.iter().find(|repository| repository.id == id);
+        if repository.is_none() {
+            warn!("{self} was downloaded from {id}, which no POM declares");
+        }
+        repository.map(|repository| repository.url.clone())
+    }
+
+    /// Writes the legacy `.libs` file, containing the `group:artifact:version`
+    /// of one dependency per line. Existing consumers expect only the direct
+    /// dependencies of the POM, as returned by [`read_dependencies`].
+    pub fn write<P: AsRef<Path>>(path: P, dependencies: &[Self]) -> Result<()> {
+        let path = path.as_ref();
+        let content = dependencies
+            .iter()
+            .map(|dependency| {
+                format!(
+                    "{}:{}:{}",
+                    dependency.group_id, dependency.artifact_id, dependency.version
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        fs::write(path, content).map_err(Error::io(path))
+    }
+}
+
+/// The default local Maven repository, `~/.m2/repository`, which BuildTools
+/// installs Spigot's dependencies into.
+pub fn local_repository() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".m2/repository"))
+}
+
+/// Reads the compile scoped dependencies of a POM, with its parents,
+/// properties and dependency management applied, and the repositories hosting
+/// them.
+///
+/// # Arguments
+///
+/// * `pom_path` - The path of the `pom.xml`.
+/// * `local_repository` - The local Maven repository parents and BOMs are looked up in.
+pub fn read_dependencies<P: AsRef<Path>>(
+    pom_path: P,
+    local_repository: Option<&Path>,
+) -> Result<(EffectivePom, Vec<MavenDependency>)> {
+    let pom_path = pom_path.as_ref();
+    let pom = EffectivePom::load(pom_path, local_repository)?;
+
+    let dependencies = pom
+        .dependencies
+        .iter()
+        .filter(|dep| dep.scope.is_none() || dep.scope.as_deref() == Some("compile"))
+        .filter(|dep| dep.artifact_id != "minecraft-server")
+        .map(|dep| MavenDependency::try_from(dep).map_err(|e| Error::maven(pom_path, e)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let dependencies = locate(dependencies, local_repository, &pom.repositories);
+
+    Ok((pom, dependencies))
+}
+
+/// Resolves the full runtime classpath of a POM: its `compile` and `runtime`
+/// scoped dependencies and, transitively, theirs.
+///
+/// # Arguments
+///
+/// * `pom_path` - The path of the `pom.xml`.
+/// * `local_repository` - The local Maven repository the POMs of dependencies are
+///   read from. Without it, only the direct dependencies are returned.
+pub fn resolve_dependencies<P: AsRef<Path>>(
+    pom_path: P,
+    local_repository: Option<&Path>,
+) -> Result<(EffectivePom, Vec<MavenDependency>)> {
+    let pom = EffectivePom::load(pom_path, local_repository)?;
+    let dependencies = resolve(&pom, local_repository)?;
+
+    Ok((pom, dependencies))
+}
+
+/// Resolves the full runtime classpath of an already loaded POM, like
+/// [`resolve_dependencies`].
+pub fn resolve(pom: &EffectivePom, local_repository: Option<&Path>) -> Result<Vec<MavenDependency>> {
+    let dependencies = Resolver::new(local_repository).resolve(pom)?;
+
+    Ok(locate(dependencies, local_repository, &pom.repositories))
+}
+
+/// Sets the hosting repository of dependencies found in the local repository.
+fn locate(
+    dependencies: Vec<MavenDependency>,
+    local_repository: Option<&Path>,
+    repositories: &[PomRepository],
+) -> Vec<MavenDependency> {
+    let Some(local_repository) = local_repository else {
+        return dependencies;
+    };
+
+    dependencies
+        .into_iter()
+        .map(|dependency| MavenDependency {
+            repository: dependency.hosting_repository(local_repository, repositories),
+            ..dependency
+        })
+        .collect()
+}
@@ -0,0 +1,527 @@
+//! The effective model of a POM.
+//!
+//! Spigot's POMs inherit from a parent, take versions from
+//! `<dependencyManagement>` and use properties in their coordinates. This
+//! module resolves all of that the way Maven would before the dependencies are
+//! read, on top of the plain [`Project`] parsed by `pommes`.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use pommes::Project;
+use proc_macros::serial;
+use tracing::warn;
+
+use crate::error::{Error, Result};
+use crate::libraries::MAVEN_CENTRAL_URL;
+
+/// How many parents or imported BOMs are followed before giving up.
+const MAX_DEPTH: usize = 32;
+
+/// The parts of a POM `pommes` does not model.
+#[serial]
+#[derive(Default)]
+struct PomModel {
+    group_id: Option<String>,
+    artifact_id: Option<String>,
+    version: Option<String>,
+    packaging: Option<String>,
+    parent: Option<PomParent>,
+    #[serde(default)]
+    properties: HashMap<String, String>,
+    dependency_management: Option<DependencyManagement>,
+    dependencies: Option<PomDependencies>,
+    repositories: Option<PomRepositories>,
+}
+
+/// The `<parent>` of a POM.
+#[serial]
+pub struct PomParent {
+    pub group_id: String,
+    pub artifact_id: String,
+    pub version: String,
+    /// The path of the parent POM, `../pom.xml` if unset and none if empty.
+    pub relative_path: Option<String>,
+}
+
+#[serial]
+struct DependencyManagement {
+    dependencies: Option<PomDependencies>,
+}
+
+#[serial]
+struct PomDependencies {
+    #[serde(rename = "dependency", default)]
+    dependencies: Vec<PomDependency>,
+}
+
+#[serial]
+struct PomRepositories {
+    #[serde(rename = "repository", default)]
+    repositories: Vec<PomRepository>,
+}
+
+/// A `<repository>` of a POM.
+#[serial]
+pub struct PomRepository {
+    pub id: String,
+    pub url: String,
+}
+
+/// Maven Central, which every POM implicitly inherits from Maven's super POM.
+pub fn central() -> PomRepository {
+    PomRepository {
+        id: "central".to_owned(),
+        url: MAVEN_CENTRAL_URL.to_owned(),
+    }
+}
+
+/// A `<dependency>` of a POM or its `<dependencyManagement>`.
+#[serial]
+pub struct PomDependency {
+    pub group_id: String,
+    pub artifact_id: String,
+    pub version: Option<String>,
+    pub classifier: Option<String>,
+    /// The packaging type, `jar` if unset.
+    #[serde(rename = "type")]
+    pub packaging: Option<String>,
+    /// The Maven scope, `compile` if unset.
+    pub scope: Option<String>,
+    pub optional: Option<String>,
+    pub exclusions: Option<Exclusions>,
+}
+
+/// The `<exclusions>` of a dependency.
+#[serial]
+pub struct Exclusions {
+    #[serde(rename = "exclusion", default)]
+    pub exclusions: Vec<Exclusion>,
+}
+
+/// A dependency excluded from the transitive dependencies, `*` matching any
+/// group or artifact.
+#[serial]
+pub struct Exclusion {
+    pub group_id: String,
+    pub artifact_id: String,
+}
+
+impl PomDependency {
+    /// The `group:artifact:type[:classifier]` identifying the dependency in
+    /// `<dependencyManagement>`.
+    pub fn management_key(&self) -> String {
+        let packaging = self.packaging.as_deref().unwrap_or("jar");
+        match &self.classifier {
+            Some(classifier) => format!(
+                "{}:{}:{packaging}:{classifier}",
+                self.group_id, self.artifact_id
+            ),
+            None => format!("{}:{}:{packaging}", self.group_id, self.artifact_id),
+        }
+    }
+
+    /// Whether the dependency is marked `<optional>true</optional>`.
+    pub fn is_optional(&self) -> bool {
+        self.optional.as_deref().map(str::trim) == Some("true")
+    }
+}
+
+/// A POM with its parents, properties and dependency management applied.
+pub struct EffectivePom {
+    /// The path of the POM.
+    pub path: PathBuf,
+    /// The POM as parsed by `pommes`, without anything inherited.
+    pub project: Project,
+    pub group_id: String,
+    pub artifact_id: String,
+    pub version: String,
+    pub packaging: String,
+    /// The parent POMs that were found, nearest first.
+    pub parents: Vec<PathBuf>,
+    /// The properties of the POM and its parents, including the `project.*`
+    /// built-ins. Values are not interpolated.
+    pub properties: HashMap<String, String>,
+    /// The interpolated `<dependencyManagement>`, including imported BOMs.
+    pub managed_dependencies: Vec<PomDependency>,
+    /// The interpolated dependencies, including inherited ones, with versions
+    /// and scopes taken from the dependency management where unset. The
+    /// version is still `None` if it is not managed either.
+    pub dependencies: Vec<PomDependency>,
+    /// The interpolated repositories of the POM and its parents, nearest
+    /// first, followed by Maven Central unless a POM overrides `central`.
+    pub repositories: Vec<PomRepository>,
+}
+
+impl EffectivePom {
+    /// Reads a POM and resolves its parent chain.
+    ///
+    /// Parents are looked up at their `relativePath` first and in the local
+    /// repository second. A parent that cannot be found ends the chain with a
+    /// warning, as Maven would only fail once something it declares is needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the `pom.xml`.
+    /// * `local_repository` - The local Maven repository, usually `~/.m2/repository`.
+    pub fn load<P: AsRef<Path>>(path: P, local_repository: Option<&Path>) -> Result<Self> {
+        Self::load_nested(path.as_ref(), local_repository, 0)
+    }
+
+    fn load_nested(path: &Path, local_repository: Option<&Path>, depth: usize) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(Error::io(path))?;
+        let project =
+            serde_xml_rs::from_str::<Project>(&contents).map_err(|e| Error::maven(path, e))?;
+        let model = parse_model(path, &contents)?;
+
+        let chain = parent_chain(path, model, local_repository)?;
+        let (_, model) = &chain[0];
+
+        let parent = model.parent.as_ref();
+        let group_id = model
+            .group_id
+            .clone()
+            .or_else(|| parent.map(|parent| parent.group_id.clone()))
+            .unwrap_or_default();
+        let artifact_id = model.artifact_id.clone().unwrap_or_default();
+        let version = model
+            .version
+            .clone()
+            .or_else(|| parent.map(|parent| parent.version.clone()))
+            .unwrap_or_default();
+        let packaging = model.packaging.clone().unwrap_or_else(|| "jar".to_owned());
+
+        // Nearer POMs override the properties of their parents, the built-ins
+        // override everything.
+        let mut properties = HashMap::new();
+        for (_, model) in chain.iter().rev() {
+            properties.extend(model.properties.clone());
+        }
+        for prefix in ["project.", "pom.", ""] {
+            properties.insert(format!("{prefix}groupId"), group_id.clone());
+            properties.insert(format!("{prefix}artifactId"), artifact_id.clone());
+            properties.insert(format!("{prefix}version"), version.clone());
+            properties.insert(format!("{prefix}packaging"), packaging.clone());
+        }
+        if let Some(parent) = parent {
+            properties.insert("project.parent.groupId".to_owned(), parent.group_id.clone());
+            properties.insert("project.parent.artifactId".to_owned(), parent.artifact_id.clone());
+            properties.insert("project.parent.version".to_owned(), parent.version.clone());
+        }
+        properties.insert(
+            "project.basedir".to_owned(),
+            path.parent().unwrap_or(Path::new(".")).display().to_string(),
+        );
+
+        let mut pom = Self {
+            path: path.to_path_buf(),
+            project,
+            group_id,
+            artifact_id,
+            version,
+            packaging,
+            parents: chain[1..].iter().map(|(path, _)| path.clone()).collect(),
+            properties,
+            managed_dependencies: vec![],
+            dependencies: vec![],
+            repositories: vec![],
+        };
+        pom.group_id = pom.interpolate(&pom.group_id)?;
+        pom.version = pom.interpolate(&pom.version)?;
+
+        let declared = |dependencies: &Option<PomDependencies>| {
+            dependencies
+                .iter()
+                .flat_map(|dependencies| dependencies.dependencies.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let mut managed = vec![];
+        for (_, model) in chain.iter().rev() {
+            let dependencies = model
+                .dependency_management
+                .as_ref()
+                .map(|management| declared(&management.dependencies))
+                .unwrap_or_default();
+            managed.extend(dependencies);
+        }
+        let managed = pom.interpolate_dependencies(managed)?;
+        pom.managed_dependencies = pom.import_boms(managed, local_repository, depth)?;
+
+        let mut dependencies = vec![];
+        for (_, model) in chain.iter().rev() {
+            dependencies.extend(declared(&model.dependencies));
+        }
+        let dependencies = pom.interpolate_dependencies(dependencies)?;
+        pom.dependencies = dependencies
+            .into_iter()
+            .map(|dependency| pom.manage(dependency))
+            .collect();
+
+        let central = central();
+        let mut repositories: Vec<PomRepository> = vec![];
+        let declared = chain
+            .iter()
+            .flat_map(|(_, model)| model.repositories.iter())
+            .flat_map(|repositories| repositories.repositories.iter())
+            .chain([&central]);
+        for repository in declared {
+            let id = pom.interpolate(repository.id.trim())?;
+            if repositories.iter().all(|existing| existing.id != id) {
+                let mut url = pom.interpolate(repository.url.trim())?;
+                if !url.ends_with('/') {
+                    url.push('/');
+                }
+                repositories.push(PomRepository { id, url });
+            }
+        }
+        pom.repositories = repositories;
+
+        Ok(pom)
+    }
+
+    /// Substitutes the `${...}` properties in a value, recursively.
+    ///
+    /// Unknown properties are kept as is, like Maven does.
+    ///
+    /// # Returns
+    ///
+    /// The interpolated value, or an error if a property references itself.
+    pub fn interpolate(&self, value: &str) -> Result<String> {
+        self.interpolate_with(value, &mut vec![])
+    }
+
+    fn interpolate_with(&self, value: &str, stack: &mut Vec<String>) -> Result<String> {
+        let mut result = String::new();
+        let mut rest = value;
+
+        while let Some(start) = rest.find("${") {
+            let Some(length) = rest[start..].find('}') else {
+                break;
+            };
+            let end = start + length;
+            let key = &rest[start + 2..end];
+            result.push_str(&rest[..start]);
+
+            if stack.iter().any(|parent| parent == key) {
+                return Err(Error::maven(
+                    &self.path,
+                    format!("property {key} references itself via {}", stack.join(" -> ")),
+                ));
+            }
+            match self.properties.get(key) {
+                Some(raw) if stack.len() < MAX_DEPTH => {
+                    stack.push(key.to_owned());
+                    let resolved = self.interpolate_with(raw, stack)?;
+                    stack.pop();
+                    result.push_str(&resolved);
+                }
+                _ => result.push_str(&rest[start..=end]),
+            }
+
+            rest = &rest[end + 1..];
+        }
+
+        result.push_str(rest);
+        Ok(result)
+    }
+
+    /// Interpolates the coordinates of dependencies, the later one winning if
+    /// the same dependency is declared twice.
+    fn interpolate_dependencies(
+        &self,
+        dependencies: Vec<PomDependency>,
+    ) -> Result<Vec<PomDependency>> {
+        let optional = |value: &Option<String>| -> Result<Option<String>> {
+            value
+                .as_deref()
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(|value| self.interpolate(value))
+                .transpose()
+        };
+
+        let mut interpolated: Vec<PomDependency> = vec![];
+        for dependency in dependencies {
+            let dependency = PomDependency {
+                group_id: self.interpolate(dependency.group_id.trim())?,
+                artifact_id: self.interpolate(dependency.artifact_id.trim())?,
+                version: optional(&dependency.version)?,
+                classifier: optional(&dependency.classifier)?,
+                packaging: optional(&dependency.packaging)?,
+                scope: optional(&dependency.scope)?,
+                optional: optional(&dependency.optional)?,
+                exclusions: dependency.exclusions,
+            };
+
+            let key = dependency.management_key();
+            interpolated.retain(|existing| existing.management_key() != key);
+            interpolated.push(dependency);
+        }
+
+        Ok(interpolated)
+    }
+
+    /// Replaces the `import` scoped BOMs of a dependency management with the
+    /// dependencies they manage. Explicitly managed dependencies win.
+    fn import_boms(
+        &self,
+        managed: Vec<PomDependency>,
+        local_repository: Option<&Path>,
+        depth: usize,
+    ) -> Result<Vec<PomDependency>> {
+        let (boms, mut managed): (Vec<_>, Vec<_>) = managed.into_iter().partition(|dependency| {
+            dependency.scope.as_deref() == Some("import")
+                && dependency.packaging.as_deref() == Some("pom")
+        });
+
+        let mut keys = managed
+            .iter()
+            .map(PomDependency::management_key)
+            .collect::<HashSet<_>>();
+        for bom in boms {
+            let bom_path = match (&bom.version, local_repository) {
+                (Some(version), Some(repository)) if depth < MAX_DEPTH => {
+                    repository_pom(repository, &bom.group_id, &bom.artifact_id, version)
+                }
+                _ => None,
+            };
+            let Some(bom_path) = bom_path else {
+                warn!(
+                    "BOM {}:{} imported by {} was not found",
+                    bom.group_id,
+                    bom.artifact_id,
+                    self.path.display()
+                );
+                continue;
+            };
+
+            let imported = Self::load_nested(&bom_path, local_repository, depth + 1)?;
+            for dependency in imported.managed_dependencies {
+                if keys.insert(dependency.management_key()) {
+                    managed.push(dependency);
+                }
+            }
+        }
+
+        Ok(managed)
+    }
+
+    /// Fills in the version, scope and exclusions of a dependency from the
+    /// dependency management where it does not declare them itself.
+    fn manage(&self, mut dependency: PomDependency) -> PomDependency {
+        let key = dependency.management_key();
+        let Some(managed) = self
+            .managed_dependencies
+            .iter()
+            .find(|managed| managed.management_key() == key)
+        else {
+            return dependency;
+        };
+
+        if dependency.version.is_none() {
+            dependency.version = managed.version.clone();
+        }
+        if dependency.scope.is_none() {
+            dependency.scope = managed.scope.clone();
+        }
+        if dependency.exclusions.is_none() {
+            dependency.exclusions = managed.exclusions.clone();
+        }
+        dependency
+    }
+}
+
+fn parse_model(path: &Path, contents: &str) -> Result<PomModel> {
+    serde_xml_rs::from_str::<PomModel>(contents).map_err(|e| Error::maven(path, e))
+}
+
+/// Reads the parents of a POM.
+///
+/// # Returns
+///
+/// The POM followed by its parents, nearest first.
+fn parent_chain(
+    path: &Path,
+    model: PomModel,
+    local_repository: Option<&Path>,
+) -> Result<Vec<(PathBuf, PomModel)>> {
+    let mut visited = HashSet::from([path.to_path_buf()]);
+    let mut chain = vec![(path.to_path_buf(), model)];
+
+    loop {
+        let (child_path, child) = &chain[chain.len() - 1];
+        let Some(parent) = child.parent.clone() else {
+            break;
+        };
+
+        let Some((parent_path, parent_model)) =
+            find_parent(child_path, &parent, local_repository)?
+        else {
+            warn!(
+                "Parent {}:{}:{} of {} was not found",
+                parent.group_id,
+                parent.artifact_id,
+                parent.version,
+                child_path.display()
+            );
+            break;
+        };
+
+        if chain.len() > MAX_DEPTH || !visited.insert(parent_path.clone()) {
+            return Err(Error::maven(path, "the parent chain is cyclic"));
+        }
+        chain.push((parent_path, parent_model));
+    }
+
+    Ok(chain)
+}
+
+fn find_parent(
+    child: &Path,
+    parent: &PomParent,
+    local_repository: Option<&Path>,
+) -> Result<Option<(PathBuf, PomModel)>> {
+    let relative_path = parent.relative_path.as_deref().unwrap_or("../pom.xml").trim();
+    if !relative_path.is_empty() {
+        let mut candidate = child.parent().unwrap_or(Path::new(".")).join(relative_path);
+        if candidate.is_dir() {
+            candidate = candidate.join("pom.xml");
+        }
+
+        if candidate.is_file() {
+            let contents = fs::read_to_string(&candidate).map_err(Error::io(&candidate))?;
+            let model = parse_model(&candidate, &contents)?;
+            if model.artifact_id.as_deref() == Some(parent.artifact_id.as_str()) {
+                return Ok(Some((candidate, model)));
+            }
+        }
+    }
+
+    let Some(path) = local_repository.and_then(|repository| {
+        repository_pom(repository, &parent.group_id, &parent.artifact_id, &parent.version)
+    }) else {
+        return Ok(None);
+    };
+
+    let contents = fs::read_to_string(&path).map_err(Error::io(&path))?;
+    let model = parse_model(&path, &contents)?;
+    Ok(Some((path, model)))
+}
+
+/// The path of a POM in a local repository, if it exists.
+pub fn repository_pom(
+    repository: &Path,
+    group_id: &str,
+    artifact_id: &str,
+    version: &str,
+) -> Option<PathBuf> {
+    let path = repository
+        .join(group_id.replace('.', "/"))
+        .join(artifact_id)
+        .join(version)
+        .join(format!("{artifact_id}-{version}.pom"));
+
+    path.is_file().then_some(path)
+}
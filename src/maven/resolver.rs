@@ -0,0 +1,193 @@
+//! Resolves the transitive dependencies of a POM.
+//!
+//! The POMs of dependencies are read from a local Maven repository only, such
+//! as the one BuildTools populates while building Spigot, so resolving never
+//! touches the network.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+
+use tracing::warn;
+
+use crate::error::{Error, Result};
+use crate::maven::pom::{repository_pom, EffectivePom, Exclusion, PomDependency};
+use crate::maven::MavenDependency;
+
+/// A dependency waiting to be added to the classpath.
+struct Node {
+    dependency: PomDependency,
+    /// The mediated scope, `compile` or `runtime`.
+    scope: String,
+    /// The exclusions of the dependency and everything that led to it.
+    exclusions: Vec<Exclusion>,
+}
+
+/// Walks the dependency graph of a POM like Maven does: exclusions apply to
+/// the whole subtree, optional transitive dependencies are skipped and the
+/// version nearest to the root wins.
+pub struct Resolver<'a> {
+    repository: Option<&'a Path>,
+    /// The dependencies of already read POMs, by `group:artifact:version`.
+    poms: HashMap<String, Vec<PomDependency>>,
+}
+
+impl<'a> Resolver<'a> {
+    /// Creates a resolver reading POMs from a local repository. Without one,
+    /// only the direct dependencies are resolved.
+    pub fn new(repository: Option<&'a Path>) -> Self {
+        Self {
+            repository,
+            poms: HashMap::new(),
+        }
+    }
+
+    /// Resolves the runtime classpath of a POM.
+    ///
+    /// # Returns
+    ///
+    /// The `compile` and `runtime` scoped dependencies, nearest first. Their
+    /// scope is the mediated one.
+    pub fn resolve(&mut self, root: &EffectivePom) -> Result<Vec<MavenDependency>> {
+        let mut queue = root
+            .dependencies
+            .iter()
+            .filter(|dependency| dependency.artifact_id != "minecraft-server")
+            .filter_map(|dependency| {
+                let scope = mediate("compile", dependency.scope.as_deref())?;
+                Some(Node {
+                    scope,
+                    exclusions: exclusions(dependency),
+                    dependency: dependency.clone(),
+                })
+            })
+            .collect::<VecDeque<_>>();
+
+        let mut seen = HashSet::new();
+        let mut classpath = vec![];
+
+        while let Some(node) = queue.pop_front() {
+            // The queue is breadth first, so the first version seen is the nearest.
+            if !seen.insert(node.dependency.management_key()) {
+                continue;
+            }
+
+            let mut dependency = MavenDependency::try_from(&node.dependency)
+                .map_err(|e| Error::maven(&root.path, e))?;
+            dependency.scope = Some(node.scope.clone());
+
+            for child in self.dependencies_of(&dependency) {
+                if child.is_optional() || is_excluded(&node.exclusions, &child) {
+                    continue;
+                }
+                let child = manage(root, child);
+                let Some(scope) = mediate(&node.scope, child.scope.as_deref()) else {
+                    continue;
+                };
+                if let Err(err) = MavenDependency::try_from(&child) {
+                    warn!("Skipping {err}, a dependency of {dependency}");
+                    continue;
+                }
+
+                let mut child_exclusions = node.exclusions.clone();
+                child_exclusions.extend(exclusions(&child));
+                queue.push_back(Node {
+                    dependency: child,
+                    scope,
+                    exclusions: child_exclusions,
+                });
+            }
+
+            classpath.push(dependency);
+        }
+
+        Ok(classpath)
+    }
+
+    /// Reads the dependencies a dependency declares in its POM. A POM that is
+    /// missing or cannot be read only ends the dependency's subtree with a
+    /// warning.
+    fn dependencies_of(&mut self, dependency: &MavenDependency) -> Vec<PomDependency> {
+        let Some(repository) = self.repository else {
+            return vec![];
+        };
+
+        let key = format!(
+            "{}:{}:{}",
+            dependency.group_id, dependency.artifact_id, dependency.version
+        );
+        if let Some(dependencies) = self.poms.get(&key) {
+            return dependencies.clone();
+        }
+
+        let dependencies = match repository_pom(
+            repository,
+            &dependency.group_id,
+            &dependency.artifact_id,
+            &dependency.version,
+        ) {
+            Some(path) => match EffectivePom::load(&path, Some(repository)) {
+                Ok(pom) => pom.dependencies,
+                Err(err) => {
+                    warn!("Not resolving the dependencies of {dependency}: {err}");
+                    vec![]
+                }
+            },
+            None => {
+                warn!("The POM of {dependency} is not in {}", repository.display());
+                vec![]
+            }
+        };
+
+        self.poms.insert(key, dependencies.clone());
+        dependencies
+    }
+}
+
+/// The scope of a transitive dependency, or `None` if it is not on the runtime
+/// classpath.
+///
+/// # Arguments
+///
+/// * `parent` - The mediated scope of the dependency declaring it.
+/// * `scope` - The scope it is declared with.
+fn mediate(parent: &str, scope: Option<&str>) -> Option<String> {
+    match scope {
+        None | Some("compile") => Some(parent.to_owned()),
+        Some("runtime") => Some("runtime".to_owned()),
+        _ => None,
+    }
+}
+
+/// Applies the root's dependency management, which overrides the versions and
+/// scopes of transitive dependencies.
+fn manage(root: &EffectivePom, mut dependency: PomDependency) -> PomDependency {
+    let key = dependency.management_key();
+    if let Some(managed) = root
+        .managed_dependencies
+        .iter()
+        .find(|managed| managed.management_key() == key)
+    {
+        if managed.version.is_some() {
+            dependency.version = managed.version.clone();
+        }
+        if managed.scope.is_some() {
+            dependency.scope = managed.scope.clone();
+        }
+    }
+    dependency
+}
+
+fn exclusions(dependency: &PomDependency) -> Vec<Exclusion> {
+    dependency
+        .exclusions
+        .as_ref()
+        .map(|exclusions| exclusions.exclusions.clone())
+        .unwrap_or_default()
+}
+
+fn is_excluded(exclusions: &[Exclusion], dependency: &PomDependency) -> bool {
+    exclusions.iter().any(|exclusion| {
+        (exclusion.group_id == "*" || exclusion.group_id == dependency.group_id)
+            && (exclusion.artifact_id == "*" || exclusion.artifact_id == dependency.artifact_id)
+    })
+}
@@ -0,0 +1,396 @@
+//! Rules from the configuration adjusting the dependencies of a version.
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::path::Path;
+use std::str::FromStr;
+
+use proc_macros::serial_snake;
+use tracing::info;
+
+use crate::config::CONFIG_FILE;
+use crate::error::{Error, Result};
+use crate::maven::pom::PomRepository;
+use crate::maven::MavenDependency;
+
+/// What a [`LibraryRule`] does with the dependencies it matches.
+#[serial_snake]
+#[derive(Copy, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    /// Replaces matching dependencies with `library`, keeping their scope.
+    Replace,
+    /// Removes matching dependencies.
+    Exclude,
+    /// Adds `library` unless a dependency with the same group, artifact and
+    /// classifier is already present.
+    Add,
+}
+
+/// A rule adjusting the dependencies of every version, or of the versions in
+/// `minecraft_versions`.
+///
+/// ```toml
+/// [[library_rules]]
+/// action = "replace"
+/// group_id = "org.apache.logging.log4j"
+/// artifact_id = "log4j-core"
+/// versions = "[2.0,2.17.1)"
+/// library = "org.apache.logging.log4j:log4j-core:2.17.1"
+/// ```
+#[serial_snake]
+pub struct LibraryRule {
+    pub action: RuleAction,
+    /// The group of matching dependencies, any if unset or `*`.
+    pub group_id: Option<String>,
+    /// The artifact of matching dependencies, any if unset or `*`.
+    pub artifact_id: Option<String>,
+    /// A Maven version range of matching dependencies, such as `[1.0,2.0)`.
+    pub versions: Option<String>,
+    /// A version range of the Minecraft versions the rule applies to. The rule
+    /// is global if unset.
+    pub minecraft_versions: Option<String>,
+    /// The `group:artifact:version[:classifier]` replacing or added to the
+    /// matching dependencies.
+    pub library: Option<String>,
+}
+
+/// A rule that changed the dependencies of a version, as recorded in its metadata.
+#[serial_snake]
+pub struct AppliedLibraryRule {
+    pub action: RuleAction,
+    /// The dependency the rule matched, unset for added libraries.
+    pub library: Option<String>,
+    /// The library replacing the dependency or added.
+    pub replacement: Option<String>,
+}
+
+impl LibraryRule {
+    fn applies_to(&self, minecraft_version: &str) -> Result<bool> {
+        match &self.minecraft_versions {
+            Some(range) => Ok(VersionRange::parse(range)?.contains(minecraft_version)),
+            None => Ok(true),
+        }
+    }
+
+    fn matches(&self, dependency: &MavenDependency, versions: Option<&VersionRange>) -> bool {
+        let matches = |pattern: &Option<String>, value: &str| {
+            pattern
+                .as_deref()
+                .is_none_or(|pattern| pattern == "*" || pattern == value)
+        };
+
+        matches(&self.group_id, &dependency.group_id)
+            && matches(&self.artifact_id, &dependency.artifact_id)
+            && versions.is_none_or(|range| range.contains(&dependency.version))
+    }
+
+    fn library(&self) -> Result<MavenDependency> {
+        let library = self.library.as_deref().ok_or_else(|| {
+            Error::config(CONFIG_FILE, "a replace or add library rule needs a library")
+        })?;
+        library.parse().map_err(|e| Error::config(CONFIG_FILE, e))
+    }
+}
+
+/// Applies the rules to the dependencies of a Minecraft version, in order.
+///
+/// Libraries replacing or added to the dependencies are looked up in the
+/// local repository like the dependencies themselves. If a replacement makes
+/// a dependency appear twice, only the first one is kept.
+///
+/// # Arguments
+///
+/// * `rules` - The rules from the config.
+/// * `minecraft_version` - The Minecraft version.
+/// * `dependencies` - The resolved dependencies.
+/// * `local_repository` - The local Maven repository, if any.
+/// * `repositories` - The repositories declared by the POM chain.
+///
+/// # Returns
+///
+/// The adjusted dependencies and the changes made.
+///
+/// # Errors
+///
+/// Returns [`Error::Config`] if a rule has an invalid range or library.
+pub fn apply(
+    rules: &[LibraryRule],
+    minecraft_version: &str,
+    mut dependencies: Vec<MavenDependency>,
+    local_repository: Option<&Path>,
+    repositories: &[PomRepository],
+) -> Result<(Vec<MavenDependency>, Vec<AppliedLibraryRule>)> {
+    let mut applied = vec![];
+    let locate = |library: MavenDependency| MavenDependency {
+        repository: local_repository
+            .and_then(|local_repository| library.hosting_repository(local_repository, repositories)),
+        ..library
+    };
+
+    for rule in rules {
+        if !rule.applies_to(minecraft_version)? {
+            continue;
+        }
+        let versions = rule.versions.as_deref().map(VersionRange::parse).transpose()?;
+
+        match rule.action {
+            RuleAction::Replace => {
+                let library = locate(rule.library()?);
+                for dependency in &mut dependencies {
+                    if !rule.matches(dependency, versions.as_ref()) {
+                        continue;
+                    }
+
+                    applied.push(AppliedLibraryRule {
+                        action: rule.action,
+                        library: Some(dependency.to_string()),
+                        replacement: Some(library.to_string()),
+                    });
+                    *dependency = MavenDependency {
+                        scope: dependency.scope.clone(),
+                        ..library.clone()
+                    };
+                }
+            }
+            RuleAction::Exclude => dependencies.retain(|dependency| {
+                if !rule.matches(dependency, versions.as_ref()) {
+                    return true;
+                }
+
+                applied.push(AppliedLibraryRule {
+                    action: rule.action,
+                    library: Some(dependency.to_string()),
+                    replacement: None,
+                });
+                false
+            }),
+            RuleAction::Add => {
+                let library = rule.library()?;
+                let present = dependencies.iter().any(|dependency| {
+                    dependency.group_id == library.group_id
+                        && dependency.artifact_id == library.artifact_id
+                        && dependency.classifier == library.classifier
+                });
+                if present {
+                    continue;
+                }
+
+                applied.push(AppliedLibraryRule {
+                    action: rule.action,
+                    library: None,
+                    replacement: Some(library.to_string()),
+                });
+                dependencies.push(locate(library));
+            }
+        }
+    }
+
+    let mut seen = HashSet::new();
+    dependencies.retain(|dependency| {
+        seen.insert((
+            dependency.group_id.clone(),
+            dependency.artifact_id.clone(),
+            dependency.classifier.clone(),
+        ))
+    });
+
+    for rule in &applied {
+        info!(
+            "Applied {:?} library rule to {} for {minecraft_version}",
+            rule.action,
+            rule.library.as_ref().or(rule.replacement.as_ref()).map_or("", String::as_str)
+        );
+    }
+
+    Ok((dependencies, applied))
+}
+
+/// A Maven version range, such as `[1.0,2.0)`, `(,1.5]`, `[1.2]` or a union
+/// like `[1.0,1.2),[1.5,)`. A plain version only matches itself.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VersionRange {
+    restrictions: Vec<Restriction>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Restriction {
+    /// The lower bound and whether it is inclusive.
+    lower: Option<(String, bool)>,
+    /// The upper bound and whether it is inclusive.
+    upper: Option<(String, bool)>,
+}
+
+impl VersionRange {
+    /// Parses a version range.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Config`] if the range is malformed.
+    pub fn parse(range: &str) -> Result<Self> {
+        let invalid = || Error::config(CONFIG_FILE, format!("invalid version range {range:?}"));
+        let range = range.trim();
+        if range.is_empty() {
+            return Err(invalid());
+        }
+
+        if !range.starts_with(['[', '(']) {
+            return Ok(Self {
+                restrictions: vec![Restriction {
+                    lower: Some((range.to_owned(), true)),
+                    upper: Some((range.to_owned(), true)),
+                }],
+            });
+        }
+
+        let mut restrictions = vec![];
+        let mut rest = range;
+        while !rest.is_empty() {
+            let lower_inclusive = match rest.chars().next() {
+                Some('[') => true,
+                Some('(') => false,
+                _ => return Err(invalid()),
+            };
+            let end = rest.find([']', ')']).ok_or_else(invalid)?;
+            let upper_inclusive = rest[end..].starts_with(']');
+            let bounds = &rest[1..end];
+
+            let bound = |version: &str, inclusive: bool| {
+                let version = version.trim();
+                (!version.is_empty()).then(|| (version.to_owned(), inclusive))
+            };
+            let restriction = match bounds.split_once(',') {
+                Some((lower, upper)) => Restriction {
+                    lower: bound(lower, lower_inclusive),
+                    upper: bound(upper, upper_inclusive),
+                },
+                // `[1.0]` is an exact version.
+                None if lower_inclusive && upper_inclusive && !bounds.trim().is_empty() => {
+                    Restriction {
+                        lower: bound(bounds, true),
+                        upper: bound(bounds, true),
+                    }
+                }
+                None => return Err(invalid()),
+            };
+            restrictions.push(restriction);
+
+            rest = rest[end + 1..].trim_start();
+            rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+        }
+
+        Ok(Self { restrictions })
+    }
+
+    /// Whether a version is inside the range.
+    pub fn contains(&self, version: &str) -> bool {
+        self.restrictions.iter().any(|restriction| {
+            let above = restriction.lower.as_ref().is_none_or(|(lower, inclusive)| {
+                match compare_versions(version, lower) {
+                    Ordering::Greater => true,
+                    Ordering::Equal => *inclusive,
+                    Ordering::Less => false,
+                }
+            });
+            let below = restriction.upper.as_ref().is_none_or(|(upper, inclusive)| {
+                match compare_versions(version, upper) {
+                    Ordering::Less => true,
+                    Ordering::Equal => *inclusive,
+                    Ordering::Greater => false,
+                }
+            });
+            above && below
+        })
+    }
+}
+
+impl FromStr for VersionRange {
+    type Err = Error;
+
+    fn from_str(range: &str) -> Result<Self> {
+        Self::parse(range)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Number(u64),
+    Qualifier(String),
+}
+
+/// The rank of well-known qualifiers, releases being `0`.
+fn qualifier_rank(qualifier: &str) -> Option<i8> {
+    match qualifier {
+        "alpha" | "a" => Some(-5),
+        "beta" | "b" => Some(-4),
+        "milestone" | "m" => Some(-3),
+        "rc" | "cr" | "pre" => Some(-2),
+        "snapshot" => Some(-1),
+        "" | "ga" | "final" | "release" => Some(0),
+        "sp" => Some(1),
+        _ => None,
+    }
+}
+
+fn tokens(version: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    for part in version.split(['.', '-', '_', '+']) {
+        // Split transitions between digits and letters, as in `1.0rc1`.
+        let mut start = 0;
+        let chars = part.char_indices().collect::<Vec<_>>();
+        for (i, (_, c)) in chars.iter().enumerate() {
+            let next = chars.get(i + 1);
+            let boundary = next.is_none_or(|(_, next)| c.is_ascii_digit() != next.is_ascii_digit());
+            if boundary {
+                let end = next.map_or(part.len(), |(next_index, _)| *next_index);
+                let token = &part[start..end];
+                tokens.push(match token.parse() {
+                    Ok(number) => Token::Number(number),
+                    Err(_) => Token::Qualifier(token.to_lowercase()),
+                });
+                start = end;
+            }
+        }
+    }
+    tokens
+}
+
+fn compare_tokens(a: Option<&Token>, b: Option<&Token>) -> Ordering {
+    let empty = Token::Qualifier(String::new());
+    let zero = Token::Number(0);
+    let (a, b) = match (a, b) {
+        (Some(a), Some(b)) => (a, b),
+        // A missing part equals `0` next to numbers and a release next to qualifiers.
+        (Some(a @ Token::Number(_)), None) => (a, &zero),
+        (None, Some(b @ Token::Number(_))) => (&zero, b),
+        (Some(a), None) => (a, &empty),
+        (None, Some(b)) => (&empty, b),
+        (None, None) => return Ordering::Equal,
+    };
+
+    match (a, b) {
+        (Token::Number(a), Token::Number(b)) => a.cmp(b),
+        (Token::Number(_), Token::Qualifier(_)) => Ordering::Greater,
+        (Token::Qualifier(_), Token::Number(_)) => Ordering::Less,
+        (Token::Qualifier(a), Token::Qualifier(b)) => {
+            match (qualifier_rank(a), qualifier_rank(b)) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => a.cmp(b),
+            }
+        }
+    }
+}
+
+/// Compares two Maven versions, numerically where possible, with qualifiers
+/// such as `beta` or `SNAPSHOT` sorting before the release they precede.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let a = tokens(a);
+    let b = tokens(b);
+
+    (0..a.len().max(b.len()))
+        .map(|i| compare_tokens(a.get(i), b.get(i)))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
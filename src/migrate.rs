@@ -0,0 +1,150 @@
+//! Upgrades version metadata written by older releases of bin-patch-gen.
+//!
+//! Every migration upgrades the raw JSON of one schema version to the next, so
+//! metadata of any age can be read and rewritten without rebuilding.
+
+use std::fs;
+use std::path::Path;
+
+use serde_json::{Map, Value};
+use tracing::{info, warn};
+
+use crate::config::{PatchedVersionMeta, METADATA_SCHEMA_VERSION};
+use crate::error::{Error, Result};
+use crate::signing::Signer;
+use crate::util::write_atomic;
+use crate::{index, MinecraftVersion};
+
+/// The schema version of metadata without a `schema_version` field.
+pub const LEGACY_SCHEMA_VERSION: u32 = 1;
+
+type Migration = fn(&mut Map<String, Value>);
+
+/// `MIGRATIONS[i]` upgrades schema version `i + 1` to `i + 2`.
+const MIGRATIONS: [Migration; (METADATA_SCHEMA_VERSION - LEGACY_SCHEMA_VERSION) as usize] =
+    [v1_to_v2, v2_to_v3, v3_to_v4];
+
+/// Version 2 added the Piston hash and size of the vanilla download and the
+/// applied library rules.
+fn v1_to_v2(meta: &mut Map<String, Value>) {
+    meta.entry("vanilla_download_hash").or_insert(Value::Null);
+    meta.entry("vanilla_download_size").or_insert(Value::Null);
+    meta.entry("library_rules").or_insert(Value::Array(vec![]));
+}
+
+/// Version 3 added the provenance of the build.
+fn v2_to_v3(meta: &mut Map<String, Value>) {
+    meta.entry("provenance").or_insert(Value::Null);
+}
+
+/// Version 4 added the SHA-256 and SHA-512 checksums of the patch and jars.
+/// They cannot be computed without the jars, so they stay unset.
+fn v3_to_v4(meta: &mut Map<String, Value>) {
+    meta.entry("patch_checksums").or_insert(Value::Null);
+    meta.entry("vanilla_jar_checksums").or_insert(Value::Null);
+    meta.entry("patched_jar_checksums").or_insert(Value::Null);
+}
+
+/// The schema version of raw metadata.
+pub fn schema_version(meta: &Value) -> u32 {
+    meta.get("schema_version")
+        .and_then(Value::as_u64)
+        .and_then(|version| u32::try_from(version).ok())
+        .unwrap_or(LEGACY_SCHEMA_VERSION)
+        .max(LEGACY_SCHEMA_VERSION)
+}
+
+/// Upgrades raw metadata to [`METADATA_SCHEMA_VERSION`].
+///
+/// Metadata of a newer schema is left as is; fields this release does not know
+/// are ignored when it is deserialized.
+///
+/// # Returns
+///
+/// Whether the metadata was changed.
+pub fn migrate_metadata(meta: &mut Value) -> bool {
+    let version = schema_version(meta);
+    let Some(object) = meta.as_object_mut() else {
+        return false;
+    };
+    if version >= METADATA_SCHEMA_VERSION {
+        if version > METADATA_SCHEMA_VERSION {
+            warn!("Metadata has schema version {version}, newer than {METADATA_SCHEMA_VERSION}");
+        }
+        return false;
+    }
+
+    for migration in &MIGRATIONS[(version - LEGACY_SCHEMA_VERSION) as usize..] {
+        migration(object);
+    }
+    object.insert(
+        "schema_version".to_owned(),
+        Value::from(METADATA_SCHEMA_VERSION),
+    );
+
+    true
+}
+
+/// What [`migrate_run_dir`] did.
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    /// The versions whose metadata was rewritten.
+    pub migrated: Vec<String>,
+    /// The versions whose metadata already had the current schema.
+    pub current: Vec<String>,
+}
+
+/// Rewrites the metadata of every version in a run directory, or a checkout of
+/// the patches repository, to the current schema and regenerates its index and
+/// checksum manifests. Nothing is rebuilt and no other file is touched.
+///
+/// With a `signer`, rewritten metadata, the index and the manifests are signed
+/// again, as the rewrite invalidates their signatures.
+///
+/// # Errors
+///
+/// Fails on the first metadata file that cannot be read or migrated, before
+/// the index is regenerated.
+pub fn migrate_run_dir<P: AsRef<Path>>(
+    run_dir: P,
+    signer: Option<&Signer>,
+) -> Result<MigrationReport> {
+    let run_dir = run_dir.as_ref();
+    let mut report = MigrationReport::default();
+
+    for file in fs::read_dir(run_dir).map_err(Error::io(run_dir))? {
+        let path = file.map_err(Error::io(run_dir))?.path();
+        if path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+        let Some(version) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        if MinecraftVersion::of(version.to_owned()).is_err() {
+            continue;
+        }
+
+        let what = path.display().to_string();
+        let content = fs::read_to_string(&path).map_err(Error::io(&path))?;
+        let mut meta = serde_json::from_str::<Value>(&content).map_err(Error::metadata(&what))?;
+        if !migrate_metadata(&mut meta) {
+            report.current.push(version.to_owned());
+            continue;
+        }
+
+        // Round trip through the typed metadata, so only valid files are written.
+        let meta = serde_json::from_value::<PatchedVersionMeta>(meta)
+            .map_err(Error::metadata(&what))?;
+        let json = serde_json::to_string_pretty(&meta).map_err(Error::metadata(&what))?;
+        write_atomic(&path, json)?;
+        if let Some(signer) = signer {
+            signer.sign_file(&path)?;
+        }
+
+        info!("Migrated {version} to schema version {METADATA_SCHEMA_VERSION}");
+        report.migrated.push(version.to_owned());
+    }
+
+    index::finalize(run_dir, &Default::default(), signer)?;
+    Ok(report)
+}
@@ -0,0 +1,80 @@
+//! Records how a version was built.
+
+use std::env;
+use std::fs;
+use std::time::Instant;
+
+use chrono::Utc;
+use proc_macros::serial_snake;
+
+use crate::build_tools::BuildToolsInfo;
+use crate::java::JavaInstallation;
+use crate::util::timestamp;
+
+/// The version of bin-patch-gen.
+pub const GENERATOR_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// How long a stage of a build took.
+#[serial_snake]
+pub struct Stage {
+    pub name: String,
+    pub duration_ms: u64,
+}
+
+/// How a version was built, as observed by [`crate::run`].
+#[serial_snake]
+pub struct Provenance {
+    /// The version of bin-patch-gen that built the version.
+    pub generator_version: String,
+    pub buildtools: BuildToolsInfo,
+    /// The arguments BuildTools was run with, after `java`.
+    pub buildtools_args: Vec<String>,
+    pub java: JavaInstallation,
+    /// The host name of the machine, if known.
+    pub host: Option<String>,
+    /// When the build started, in RFC 3339.
+    pub started_at: String,
+    /// When the build finished, in RFC 3339.
+    pub finished_at: String,
+    /// The stages of the build, in order.
+    pub stages: Vec<Stage>,
+}
+
+impl Provenance {
+    /// Starts recording a build.
+    pub fn start(buildtools: BuildToolsInfo, java: JavaInstallation) -> Self {
+        let now = timestamp(Utc::now());
+        Self {
+            generator_version: GENERATOR_VERSION.to_owned(),
+            buildtools,
+            buildtools_args: vec![],
+            java,
+            host: host(),
+            started_at: now.clone(),
+            finished_at: now,
+            stages: vec![],
+        }
+    }
+
+    /// Records a stage that started at `started` and ends now.
+    pub fn stage<N: Into<String>>(&mut self, name: N, started: Instant) {
+        self.stages.push(Stage {
+            name: name.into(),
+            duration_ms: started.elapsed().as_millis() as u64,
+        });
+    }
+
+    /// Records the end of the build.
+    pub fn finish(&mut self) {
+        self.finished_at = timestamp(Utc::now());
+    }
+}
+
+/// The host name, from `HOSTNAME` or `/etc/hostname`.
+fn host() -> Option<String> {
+    env::var("HOSTNAME")
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_owned())
+        .filter(|host| !host.is_empty())
+}
@@ -0,0 +1,261 @@
+//! Commits the output of a run to git and pushes it.
+//!
+//! The run directory is a checkout of the patches repository. Publishing
+//! verifies it first, then commits only the output files that changed and
+//! pushes the branch to the configured remote.
+
+use std::path::Path;
+
+use git2::{
+    Cred, CredentialType, IndexAddOption, PushOptions, RemoteCallbacks, Repository, Signature,
+};
+use tracing::{info, warn};
+
+use crate::changelog::{Changelog, CHANGELOG_FILE, CHANGELOG_SUMMARY_FILE};
+use crate::checksums::Algorithm;
+use crate::error::{Error, Result};
+use crate::history::HISTORY_DIR;
+use crate::signing::{self, VerifyingKey, PUBLIC_KEY_FILE, SIGNATURE_EXTENSION};
+use crate::{index, MinecraftVersion};
+
+/// The branch published to if none is configured.
+pub const DEFAULT_BRANCH: &str = "master";
+
+/// The author of commits if the repository has no `user.name` and `user.email`.
+const DEFAULT_AUTHOR: (&str, &str) = ("bin-patch-gen", "bin-patch-gen@localhost");
+
+/// How often credentials are asked for before pushing gives up.
+const MAX_CREDENTIAL_ATTEMPTS: usize = 3;
+
+/// Where and how to publish.
+#[derive(Debug, Clone)]
+pub struct PublishOptions {
+    /// A URL or the name of a remote of the repository. Nothing is pushed if unset.
+    pub remote: Option<String>,
+    pub branch: String,
+    /// The key the output must be signed with. Without one, only the hashes the
+    /// index lists are verified.
+    pub public_key: Option<VerifyingKey>,
+}
+
+impl Default for PublishOptions {
+    fn default() -> Self {
+        Self {
+            remote: None,
+            branch: DEFAULT_BRANCH.to_owned(),
+            public_key: None,
+        }
+    }
+}
+
+/// What [`publish`] did.
+#[derive(Debug, Default)]
+pub struct PublishReport {
+    /// The id of the new commit, unset if nothing changed.
+    pub commit: Option<String>,
+    /// The changed files, relative to the run directory.
+    pub files: Vec<String>,
+    /// Whether the branch was pushed.
+    pub pushed: bool,
+}
+
+/// Whether a path in the repository is output of bin-patch-gen. Anything else,
+/// such as temporary files, is never committed.
+pub(crate) fn is_output(path: &Path) -> bool {
+    let Some(path) = path.to_str() else {
+        return false;
+    };
+    if path.split('/').any(|component| component.ends_with(".tmp")) {
+        return false;
+    }
+    if path.starts_with(&format!("{HISTORY_DIR}/")) {
+        return true;
+    }
+    if path.contains('/') {
+        return false;
+    }
+
+    let name = path
+        .strip_suffix(&format!(".{SIGNATURE_EXTENSION}"))
+        .unwrap_or(path);
+    [PUBLIC_KEY_FILE, CHANGELOG_SUMMARY_FILE].contains(&name)
+        || Algorithm::ALL.iter().any(|algorithm| algorithm.manifest_file() == name)
+        || [".json", ".patch", ".libs"].iter().any(|suffix| name.ends_with(suffix))
+}
+
+/// The commit message: the changelog of the run if it changed, otherwise the
+/// versions whose metadata changed.
+fn commit_message(run_dir: &Path, files: &[String]) -> String {
+    if files.iter().any(|file| file == CHANGELOG_FILE) {
+        match Changelog::read(run_dir.join(CHANGELOG_FILE)) {
+            Ok(changelog) if !changelog.versions.is_empty() => return changelog.to_markdown(),
+            Ok(_) => {}
+            Err(err) => warn!("Not using the changelog as commit message: {err}"),
+        }
+    }
+
+    let mut versions = files
+        .iter()
+        .filter_map(|file| file.strip_suffix(".json"))
+        .filter_map(|version| MinecraftVersion::of(version.to_owned()).ok())
+        .collect::<Vec<_>>();
+    versions.sort();
+
+    if versions.is_empty() {
+        "Update output".to_owned()
+    } else {
+        let versions = versions.iter().map(ToString::to_string).collect::<Vec<_>>();
+        format!("Update {}\n", versions.join(", "))
+    }
+}
+
+/// Verifies a run directory, commits its changed output and pushes it.
+///
+/// The run directory is initialized as a repository if it is none yet. Its
+/// `HEAD` must be `branch`, or unborn.
+///
+/// # Errors
+///
+/// Fails without touching the repository if the output cannot be verified, and
+/// if the remote rejects the push, such as when it has commits the run
+/// directory does not.
+pub fn publish<P: AsRef<Path>>(run_dir: P, options: &PublishOptions) -> Result<PublishReport> {
+    let run_dir = run_dir.as_ref();
+
+    let index = match &options.public_key {
+        Some(key) => signing::verify_run_dir(key, run_dir)?,
+        None => index::verify(run_dir)?,
+    };
+    info!("Verified {} versions", index.versions.len());
+
+    let repo = match Repository::open(run_dir) {
+        Ok(repo) => repo,
+        Err(err) if err.code() == git2::ErrorCode::NotFound => {
+            Repository::init(run_dir).map_err(Error::git(run_dir))?
+        }
+        Err(err) => return Err(Error::git(run_dir)(err)),
+    };
+
+    let reference = format!("refs/heads/{}", options.branch);
+    let head = repo.find_reference("HEAD").map_err(Error::git(run_dir))?;
+    let parent = match head.symbolic_target() {
+        Some(target) if target == reference => repo
+            .find_reference(&reference)
+            .ok()
+            .map(|branch| branch.peel_to_commit())
+            .transpose()
+            .map_err(Error::git(run_dir))?,
+        _ if repo.head().is_err() => {
+            repo.set_head(&reference).map_err(Error::git(run_dir))?;
+            None
+        }
+        target => {
+            let message = format!(
+                "HEAD is {}, not {reference}",
+                target.unwrap_or("detached")
+            );
+            return Err(Error::git(run_dir)(git2::Error::from_str(&message)));
+        }
+    };
+
+    let mut git_index = repo.index().map_err(Error::git(run_dir))?;
+    let mut filter = |path: &Path, _: &[u8]| if is_output(path) { 0 } else { 1 };
+    git_index
+        .update_all(["*"], Some(&mut filter))
+        .map_err(Error::git(run_dir))?;
+    git_index
+        .add_all(["*"], IndexAddOption::DEFAULT, Some(&mut filter))
+        .map_err(Error::git(run_dir))?;
+    git_index.write().map_err(Error::git(run_dir))?;
+    let tree = git_index
+        .write_tree()
+        .and_then(|tree| repo.find_tree(tree))
+        .map_err(Error::git(run_dir))?;
+
+    let parent_tree = parent
+        .as_ref()
+        .map(|parent| parent.tree())
+        .transpose()
+        .map_err(Error::git(run_dir))?;
+    let diff = repo
+        .diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)
+        .map_err(Error::git(run_dir))?;
+    let files = diff
+        .deltas()
+        .filter_map(|delta| delta.new_file().path().or_else(|| delta.old_file().path()))
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+
+    let mut report = PublishReport {
+        files,
+        ..Default::default()
+    };
+    if report.files.is_empty() {
+        info!("Nothing to commit");
+    } else {
+        let signature = repo
+            .signature()
+            .or_else(|_| Signature::now(DEFAULT_AUTHOR.0, DEFAULT_AUTHOR.1))
+            .map_err(Error::git(run_dir))?;
+        let message = commit_message(run_dir, &report.files);
+        let parents = parent.iter().collect::<Vec<_>>();
+        let commit = repo
+            .commit(Some(&reference), &signature, &signature, &message, &tree, &parents)
+            .map_err(Error::git(run_dir))?;
+
+        info!("Committed {} files as {commit}", report.files.len());
+        report.commit = Some(commit.to_string());
+    }
+
+    match &options.remote {
+        Some(remote) => {
+            push(&repo, remote, &reference).map_err(Error::git(run_dir))?;
+            info!("Pushed {reference} to {remote}");
+            report.pushed = true;
+        }
+        None => warn!("No remote configured, not pushing"),
+    }
+
+    Ok(report)
+}
+
+/// Pushes a branch, failing if the remote rejects it.
+fn push(repo: &Repository, remote: &str, reference: &str) -> std::result::Result<(), git2::Error> {
+    let mut remote = repo
+        .find_remote(remote)
+        .or_else(|_| repo.remote_anonymous(remote))?;
+
+    let mut attempts = 0;
+    let mut rejection = None;
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(|url, username, allowed| {
+        attempts += 1;
+        if attempts > MAX_CREDENTIAL_ATTEMPTS {
+            return Err(git2::Error::from_str("no valid credentials"));
+        }
+        if allowed.contains(CredentialType::SSH_KEY) {
+            return Cred::ssh_key_from_agent(username.unwrap_or("git"));
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            let config = git2::Config::open_default()?;
+            return Cred::credential_helper(&config, url, username);
+        }
+        Cred::default()
+    });
+    callbacks.push_update_reference(|reference, status| {
+        if let Some(status) = status {
+            rejection = Some(format!("{reference} was rejected: {status}"));
+        }
+        Ok(())
+    });
+
+    let mut options = PushOptions::new();
+    options.remote_callbacks(callbacks);
+    remote.push(&[format!("{reference}:{reference}")], Some(&mut options))?;
+    drop(options);
+
+    match rejection {
+        Some(rejection) => Err(git2::Error::from_str(&rejection)),
+        None => Ok(()),
+    }
+}
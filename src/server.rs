@@ -0,0 +1,359 @@
+//! Serves the run directory over HTTP.
+//!
+//! The output files are served at the root, such as `/index.json` and
+//! `/{version}.patch`, and archived builds at `/history/{version}/{build}/`,
+//! with ETags and single byte range requests. Optionally,
+//! `/{version}/server.jar` serves the patched server jar, which is built from
+//! the vanilla jar and the patch on the first request and cached.
+
+use std::fs;
+use std::io::{self, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use axum::body::Body;
+use axum::extract::{Path as UrlPath, State};
+use axum::http::header::{
+    ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+    IF_NONE_MATCH, IF_RANGE, RANGE,
+};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use futures_util::stream;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+use crate::config::PatchedVersionMeta;
+use crate::history::HISTORY_DIR;
+use crate::error::{Error, Result};
+use crate::http::{self, Integrity};
+use crate::jar::bundler::unbundle;
+use crate::signing::VerifyingKey;
+use crate::util::content_type;
+use crate::{patch_checked, publish, MinecraftVersion};
+
+/// The address served on if none is configured.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8000";
+
+/// The directory patched jars are cached in, relative to the cache directory.
+pub const PATCHED_JAR_DIR: &str = "patched";
+
+/// The size of the chunks files are streamed in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// What to serve besides the output files.
+#[derive(Debug, Clone, Default)]
+pub struct ServeOptions {
+    /// Whether `/{version}/server.jar` serves patched server jars.
+    pub patched_jars: bool,
+    /// The directory the vanilla and patched jars are cached in.
+    pub cache_dir: PathBuf,
+    /// The key the metadata must be signed with before a jar is patched. Without
+    /// one, only the hashes the metadata lists are checked.
+    pub public_key: Option<VerifyingKey>,
+}
+
+#[derive(Clone)]
+struct Server {
+    run_dir: PathBuf,
+    options: ServeOptions,
+    /// Held while a jar is patched, so every jar is only patched once.
+    patching: Arc<Mutex<()>>,
+}
+
+/// The routes serving a run directory.
+pub fn router<P: Into<PathBuf>>(run_dir: P, options: ServeOptions) -> Router {
+    let server = Server {
+        run_dir: run_dir.into(),
+        options,
+        patching: Arc::default(),
+    };
+
+    Router::new()
+        .route("/{file}", get(output_file))
+        .route(&format!("/{HISTORY_DIR}/{{version}}/{{build}}/{{file}}"), get(archived_file))
+        .route("/{version}/server.jar", get(patched_jar))
+        .with_state(server)
+}
+
+/// Serves a run directory until the process exits.
+///
+/// # Arguments
+///
+/// * `listener` - The listener to accept connections on.
+/// * `run_dir` - The run directory.
+/// * `options` - What to serve besides the output files.
+pub async fn serve<P: Into<PathBuf>>(listener: TcpListener, run_dir: P, options: ServeOptions) -> Result<()> {
+    let address = listener
+        .local_addr()
+        .map(|address| address.to_string())
+        .unwrap_or_default();
+
+    info!("Serving on http://{address}");
+    axum::serve(listener, router(run_dir, options))
+        .await
+        .map_err(Error::io(address))
+}
+
+async fn output_file(
+    State(server): State<Server>,
+    UrlPath(name): UrlPath<String>,
+    headers: HeaderMap,
+) -> Response {
+    if name.contains('/') {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    match output_path(&server.run_dir, &name) {
+        Some(path) => file_response(&path, content_type(&name), &headers).await,
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn archived_file(
+    State(server): State<Server>,
+    UrlPath((version, build, file)): UrlPath<(String, String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    if [&version, &build, &file].iter().any(|part| part.contains('/')) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    match output_path(&server.run_dir, &format!("{HISTORY_DIR}/{version}/{build}/{file}")) {
+        Some(path) => file_response(&path, content_type(&file), &headers).await,
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// The path of an output file in the run directory.
+///
+/// # Arguments
+///
+/// * `run_dir` - The run directory.
+/// * `name` - The file, relative to the run directory with `/` as separator.
+///
+/// # Returns
+///
+/// The canonical path, or `None` if the file is no output, does not exist or
+/// is outside the run directory, such as through `..` or a symbolic link.
+fn output_path(run_dir: &Path, name: &str) -> Option<PathBuf> {
+    let relative = Path::new(name);
+    let normal = relative.components().all(|component| match component {
+        Component::Normal(part) => !part.to_string_lossy().starts_with('.'),
+        _ => false,
+    });
+    if !normal || !publish::is_output(relative) {
+        return None;
+    }
+
+    let run_dir = run_dir.canonicalize().ok()?;
+    let path = run_dir.join(relative).canonicalize().ok()?;
+    path.starts_with(&run_dir).then_some(path)
+}
+
+async fn patched_jar(
+    State(server): State<Server>,
+    UrlPath(version): UrlPath<String>,
+    headers: HeaderMap,
+) -> Response {
+    let metadata = server.run_dir.join(format!("{version}.json"));
+    if !server.options.patched_jars
+        || MinecraftVersion::of(version.clone()).is_err()
+        || !metadata.is_file()
+    {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    match server.patched_jar(&version, &metadata).await {
+        Ok(jar) => {
+            let mut response = file_response(&jar, content_type("server.jar"), &headers).await;
+            let disposition = format!("attachment; filename=\"spigot-{version}.jar\"");
+            if let Ok(disposition) = disposition.parse() {
+                response.headers_mut().insert(CONTENT_DISPOSITION, disposition);
+            }
+            response
+        }
+        Err(err) => {
+            error!("Failed patching {version}: {err}");
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+        }
+    }
+}
+
+impl Server {
+    /// The patched jar of a version, from the cache or patched now. Cached jars
+    /// are named by their hash, so a rebuilt version is patched again.
+    async fn patched_jar(&self, version: &str, metadata: &Path) -> Result<PathBuf> {
+        let meta = PatchedVersionMeta::read(metadata)?;
+        let dir = self.options.cache_dir.join(PATCHED_JAR_DIR).join(version);
+        let jar = dir.join(format!("{}.jar", meta.patched_jar_hash));
+        if jar.is_file() {
+            return Ok(jar);
+        }
+
+        let _patching = self.patching.lock().await;
+        if jar.is_file() {
+            return Ok(jar);
+        }
+
+        info!("Patching the server jar of {version}");
+        let vanilla_dir = dir.join("vanilla");
+        fs::create_dir_all(&vanilla_dir).map_err(Error::io(&vanilla_dir))?;
+        let bundle = vanilla_dir.join("server.jar");
+        let integrity = match (&meta.vanilla_download_hash, meta.vanilla_download_size) {
+            (Some(hash), Some(size)) => Integrity::sha1(hash).with_size(size),
+            (Some(hash), None) => Integrity::sha1(hash),
+            (None, _) => Integrity::default(),
+        };
+        http::download_verified(&meta.vanilla_download_url, &bundle, &integrity).await?;
+        let vanilla_jar = unbundle(&bundle, &vanilla_dir)?;
+
+        let patch = self.run_dir.join(&meta.patch_file);
+        let metadata = metadata.to_path_buf();
+        let temp = dir.join(format!("{}.jar.tmp", meta.patched_jar_hash));
+        let public_key = self.options.public_key.as_ref();
+        patch_checked(&vanilla_jar, &temp, &patch, &metadata, public_key).await?;
+        fs::rename(&temp, &jar).map_err(Error::io(&jar))?;
+
+        // Jars of earlier builds of the version are never served again.
+        for file in fs::read_dir(&dir).map_err(Error::io(&dir))? {
+            let path = file.map_err(Error::io(&dir))?.path();
+            if path.is_file() && path != jar {
+                fs::remove_file(&path).map_err(Error::io(&path))?;
+            }
+        }
+
+        Ok(jar)
+    }
+}
+
+/// The part of a file a `Range` header asks for.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ByteRange {
+    /// The whole file, if there is no range or it is malformed or has several
+    /// ranges, which servers may ignore.
+    Full,
+    /// The bytes from the first to the second offset, inclusive.
+    Partial(u64, u64),
+    /// A range starting past the end of the file.
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// Parses a `Range` header for a file of `length` bytes.
+    pub fn parse(header: Option<&str>, length: u64) -> Self {
+        let Some(range) = header.and_then(|header| header.trim().strip_prefix("bytes=")) else {
+            return ByteRange::Full;
+        };
+        let Some((start, end)) = range.split_once('-').filter(|_| !range.contains(',')) else {
+            return ByteRange::Full;
+        };
+
+        let (start, end) = match (start.trim().parse::<u64>(), end.trim().parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => (start, end.min(length.saturating_sub(1))),
+            (Ok(start), Err(_)) if end.trim().is_empty() => (start, length.saturating_sub(1)),
+            (Err(_), Ok(suffix)) if start.trim().is_empty() => {
+                if suffix == 0 {
+                    return ByteRange::Unsatisfiable;
+                }
+                (length.saturating_sub(suffix), length.saturating_sub(1))
+            }
+            _ => return ByteRange::Full,
+        };
+
+        if start >= length {
+            ByteRange::Unsatisfiable
+        } else {
+            ByteRange::Partial(start, end)
+        }
+    }
+}
+
+/// The ETag of a file, from its size and modification time.
+fn etag(metadata: &fs::Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+
+    format!("\"{:x}-{:x}\"", metadata.len(), modified.as_nanos())
+}
+
+/// Whether an `If-None-Match` header matches an ETag.
+fn matches_etag(header: &str, etag: &str) -> bool {
+    header
+        .split(',')
+        .map(|candidate| candidate.trim().trim_start_matches("W/"))
+        .any(|candidate| candidate == "*" || candidate == etag)
+}
+
+/// Serves a file, honoring `If-None-Match`, `Range` and `If-Range`. Only the
+/// requested bytes are read, as the response is streamed.
+async fn file_response(path: &Path, content_type: &'static str, request: &HeaderMap) -> Response {
+    let header = |name| request.get(name).and_then(|value| value.to_str().ok());
+    let Ok(mut file) = File::open(path).await else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let metadata = match file.metadata().await {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let etag = etag(&metadata);
+    if header(IF_NONE_MATCH).is_some_and(|header| matches_etag(header, &etag)) {
+        return (StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response();
+    }
+
+    let length = metadata.len();
+    let range = match header(IF_RANGE) {
+        Some(if_range) if if_range != etag => ByteRange::Full,
+        _ => ByteRange::parse(header(RANGE), length),
+    };
+    let (status, start, end) = match range {
+        ByteRange::Full => (StatusCode::OK, 0, length.saturating_sub(1)),
+        ByteRange::Partial(start, end) => (StatusCode::PARTIAL_CONTENT, start, end),
+        ByteRange::Unsatisfiable => {
+            return (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(CONTENT_RANGE, format!("bytes */{length}"))],
+            )
+                .into_response();
+        }
+    };
+    let content_length = if length == 0 { 0 } else { end - start + 1 };
+
+    if let Err(err) = file.seek(SeekFrom::Start(start)).await {
+        error!("Failed reading {}: {err}", path.display());
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    let chunks = stream::try_unfold(file.take(content_length), |mut reader| async move {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let read = reader.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok::<_, io::Error>(None);
+        }
+        chunk.truncate(read);
+        Ok(Some((chunk, reader)))
+    });
+
+    let headers = [
+        (CONTENT_TYPE, content_type.to_owned()),
+        (CONTENT_LENGTH, content_length.to_string()),
+        (ETAG, etag),
+        (ACCEPT_RANGES, "bytes".to_owned()),
+    ];
+    let body = Body::from_stream(chunks);
+    match status {
+        StatusCode::PARTIAL_CONTENT => {
+            let content_range = format!("bytes {start}-{end}/{length}");
+            (status, headers, [(CONTENT_RANGE, content_range)], body).into_response()
+        }
+        _ => (status, headers, body).into_response(),
+    }
+}
@@ -0,0 +1,166 @@
+//! Ed25519 signatures of the metadata and the index.
+//!
+//! Every signed file `{file}` gets a detached signature `{file}.sig`, the hex
+//! encoded signature of its exact bytes. The public key is published in the
+//! run directory as [`PUBLIC_KEY_FILE`]. Since the metadata contains the hashes
+//! of the patch and jars, and the index those of the metadata, verifying the
+//! signatures covers everything a launcher downloads.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ed25519_dalek::{Signature, Signer as _, SigningKey};
+pub use ed25519_dalek::VerifyingKey;
+use tracing::info;
+
+use crate::config::PatchedVersionMeta;
+use crate::error::{Error, Result};
+use crate::index::{self, Index, INDEX_FILE};
+use crate::util::{sha1, sha256};
+
+/// The file the public key is published in, relative to the run directory.
+pub const PUBLIC_KEY_FILE: &str = "signing_key.pub";
+
+/// The extension appended to signed files for their signature.
+pub const SIGNATURE_EXTENSION: &str = "sig";
+
+/// The path of the signature of a file.
+pub fn signature_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(SIGNATURE_EXTENSION);
+    path.with_file_name(name)
+}
+
+/// Reads a hex encoded key of exactly `N` bytes.
+fn read_key<const N: usize>(path: &Path) -> Result<[u8; N]> {
+    let content = fs::read_to_string(path).map_err(Error::io(path))?;
+    let bytes = hex::decode(content.trim()).map_err(|e| Error::signature(path, e))?;
+
+    bytes
+        .try_into()
+        .map_err(|bytes: Vec<u8>| Error::signature(path, format!("expected a {N} byte key, got {} bytes", bytes.len())))
+}
+
+/// Signs files with a private key.
+pub struct Signer {
+    key: SigningKey,
+}
+
+impl Signer {
+    /// Reads a private key, the hex encoded 32 byte Ed25519 seed.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let seed = read_key::<32>(path.as_ref())?;
+
+        Ok(Self {
+            key: SigningKey::from_bytes(&seed),
+        })
+    }
+
+    pub fn public_key(&self) -> VerifyingKey {
+        self.key.verifying_key()
+    }
+
+    /// Writes `{path}.sig`.
+    pub fn sign_file<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        let path = path.as_ref();
+        let content = fs::read(path).map_err(Error::io(path))?;
+        let signature = self.key.sign(&content);
+
+        let signature_path = signature_path(path);
+        fs::write(&signature_path, hex::encode(signature.to_bytes()))
+            .map_err(Error::io(&signature_path))?;
+        Ok(signature_path)
+    }
+
+    /// Writes the hex encoded public key to [`PUBLIC_KEY_FILE`] in a directory.
+    pub fn publish_public_key<P: AsRef<Path>>(&self, directory: P) -> Result<()> {
+        let path = directory.as_ref().join(PUBLIC_KEY_FILE);
+        fs::write(&path, hex::encode(self.public_key().to_bytes())).map_err(Error::io(&path))
+    }
+}
+
+/// Reads a public key, as published in [`PUBLIC_KEY_FILE`].
+pub fn read_public_key<P: AsRef<Path>>(path: P) -> Result<VerifyingKey> {
+    let path = path.as_ref();
+    let bytes = read_key::<32>(path)?;
+
+    VerifyingKey::from_bytes(&bytes).map_err(|e| Error::signature(path, e))
+}
+
+/// Checks the `{path}.sig` of a file.
+pub fn verify_file<P: AsRef<Path>>(key: &VerifyingKey, path: P) -> Result<()> {
+    let path = path.as_ref();
+    let signature_path = signature_path(path);
+    if !signature_path.is_file() {
+        return Err(Error::signature(path, "the file is not signed"));
+    }
+
+    let signature = read_key::<64>(&signature_path)?;
+    let content = fs::read(path).map_err(Error::io(path))?;
+
+    key.verify_strict(&content, &Signature::from_bytes(&signature))
+        .map_err(|e| Error::signature(path, e))
+}
+
+fn verify_hash(path: &Path, expected: &str) -> Result<()> {
+    let actual = sha1(path)?;
+    if actual.eq_ignore_ascii_case(expected) {
+        Ok(())
+    } else {
+        Err(Error::signature(path, format!("has sha1 {actual}, but the signed hash is {expected}")))
+    }
+}
+
+fn verify_sha256(path: &Path, expected: &str) -> Result<()> {
+    let actual = sha256(path)?;
+    if actual.eq_ignore_ascii_case(expected) {
+        Ok(())
+    } else {
+        Err(Error::signature(path, format!("has sha256 {actual}, but the signed hash is {expected}")))
+    }
+}
+
+/// Checks the signature of a version's metadata and the hashes of its patch.
+///
+/// # Returns
+///
+/// The verified metadata.
+pub fn verify_patch<P: AsRef<Path>, P1: AsRef<Path>>(
+    key: &VerifyingKey,
+    metadata: P,
+    patch: P1,
+) -> Result<PatchedVersionMeta> {
+    let metadata = metadata.as_ref();
+    verify_file(key, metadata)?;
+
+    let meta = PatchedVersionMeta::read(metadata)?;
+    verify_hash(patch.as_ref(), &meta.patch_hash)?;
+    if let Some(checksums) = &meta.patch_checksums {
+        verify_sha256(patch.as_ref(), &checksums.sha256)?;
+    }
+
+    Ok(meta)
+}
+
+/// Checks the signature of `index.json` and, for every version it lists, the
+/// hashes of its files and its history and the signature of its metadata.
+///
+/// # Returns
+///
+/// The verified index.
+pub fn verify_run_dir<P: AsRef<Path>>(key: &VerifyingKey, run_dir: P) -> Result<Index> {
+    let run_dir = run_dir.as_ref();
+    verify_file(key, run_dir.join(INDEX_FILE))?;
+    // The signed index covers the hashes of the history, so archived builds
+    // need no signature of their own.
+    let index = index::verify(run_dir)?;
+
+    for entry in &index.versions {
+        verify_patch(key, run_dir.join(&entry.metadata.name), run_dir.join(&entry.patch.name))?;
+        info!("Verified {}", entry.version);
+    }
+
+    Ok(index)
+}
@@ -0,0 +1,91 @@
+//! Commits the output to a git repository.
+
+use std::path::{Path, PathBuf};
+
+use crate::publish::{self, PublishOptions};
+use crate::signing::VerifyingKey;
+use crate::sink::local::{copy_files, remove_files};
+use crate::sink::{OutputSink, SinkFuture};
+
+/// A checkout of the patches repository, which is committed to and pushed
+/// with [`publish::publish`] when the run finishes.
+pub struct GitSink {
+    checkout: PathBuf,
+    options: PublishOptions,
+}
+
+impl GitSink {
+    /// # Arguments
+    ///
+    /// * `checkout` - The checkout, which may be the run directory itself.
+    /// * `remote` - A URL or the name of a remote of the checkout.
+    /// * `branch` - The branch to commit to.
+    /// * `public_key` - The key the output must be signed with.
+    pub fn new(
+        checkout: PathBuf,
+        remote: Option<String>,
+        branch: Option<String>,
+        public_key: Option<VerifyingKey>,
+    ) -> Self {
+        let defaults = PublishOptions::default();
+
+        Self {
+            checkout,
+            options: PublishOptions {
+                remote,
+                branch: branch.unwrap_or(defaults.branch),
+                public_key,
+            },
+        }
+    }
+
+    fn is_run_dir(&self, run_dir: &Path) -> bool {
+        if self.checkout == run_dir {
+            return true;
+        }
+        match (self.checkout.canonicalize(), run_dir.canonicalize()) {
+            (Ok(checkout), Ok(run_dir)) => checkout == run_dir,
+            _ => false,
+        }
+    }
+}
+
+impl OutputSink for GitSink {
+    fn describe(&self) -> String {
+        match &self.options.remote {
+            Some(remote) => format!("git {} ({remote})", self.checkout.display()),
+            None => format!("git {}", self.checkout.display()),
+        }
+    }
+
+    fn upload<'a>(&'a self, run_dir: &'a Path, files: &'a [String]) -> SinkFuture<'a> {
+        Box::pin(async move {
+            if self.is_run_dir(run_dir) {
+                return Ok(());
+            }
+            copy_files(run_dir, &self.checkout, files)
+        })
+    }
+
+    fn remove<'a>(&'a self, run_dir: &'a Path, files: &'a [String]) -> SinkFuture<'a> {
+        Box::pin(async move {
+            // Files removed from the run directory are committed as removed.
+            if self.is_run_dir(run_dir) {
+                return Ok(());
+            }
+            remove_files(&self.checkout, files)
+        })
+    }
+
+    fn finish<'a>(&'a self, _run_dir: &'a Path) -> SinkFuture<'a> {
+        Box::pin(async move {
+            // Committing and pushing block, so they run off the async runtime.
+            let checkout = self.checkout.clone();
+            let options = self.options.clone();
+            tokio::task::spawn_blocking(move || publish::publish(&checkout, &options))
+                .await
+                .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+                .map(|_| ())
+        })
+    }
+}
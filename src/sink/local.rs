@@ -0,0 +1,77 @@
+//! Copies the output to a local directory.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::sink::{OutputSink, SinkFuture};
+
+/// Copies files into `to`, keeping their paths relative to `from`. Every file
+/// is copied next to its destination first and then renamed, so readers never
+/// see a partial file.
+pub(crate) fn copy_files(from: &Path, to: &Path, files: &[String]) -> Result<()> {
+    for name in files {
+        let source = from.join(name);
+        let target = to.join(name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(Error::io(parent))?;
+        }
+
+        let mut temp_name = target.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        let temp = target.with_file_name(temp_name);
+        fs::copy(&source, &temp).map_err(Error::io(&source))?;
+        fs::rename(&temp, &target).map_err(Error::io(&target))?;
+    }
+
+    Ok(())
+}
+
+/// Removes files from `dir`, given relative to it, and the directories they
+/// leave empty. Files that do not exist are skipped.
+pub(crate) fn remove_files(dir: &Path, files: &[String]) -> Result<()> {
+    for name in files {
+        let target = dir.join(name);
+        match fs::remove_file(&target) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(Error::io(&target)(err)),
+        }
+
+        let mut parent = target.parent();
+        while let Some(empty) = parent.filter(|parent| parent.starts_with(dir) && *parent != dir) {
+            if fs::remove_dir(empty).is_err() {
+                break;
+            }
+            parent = empty.parent();
+        }
+    }
+
+    Ok(())
+}
+
+/// A directory, such as one served by a web server.
+pub struct LocalSink {
+    dir: PathBuf,
+}
+
+impl LocalSink {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl OutputSink for LocalSink {
+    fn describe(&self) -> String {
+        self.dir.display().to_string()
+    }
+
+    fn upload<'a>(&'a self, run_dir: &'a Path, files: &'a [String]) -> SinkFuture<'a> {
+        Box::pin(async move { copy_files(run_dir, &self.dir, files) })
+    }
+
+    fn remove<'a>(&'a self, _run_dir: &'a Path, files: &'a [String]) -> SinkFuture<'a> {
+        Box::pin(async move { remove_files(&self.dir, files) })
+    }
+}
@@ -0,0 +1,224 @@
+//! Destinations the output of a run is uploaded to.
+//!
+//! A run writes its output to the run directory first. Once a version is built
+//! and verified, its files are uploaded to every configured [`OutputSink`];
+//! the index, checksum manifests and changelog follow at the end of the run.
+//! Archived builds pruned from the history are then removed from the sinks as
+//! well; sinks never delete any other file.
+
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+use proc_macros::serial_snake;
+use tracing::info;
+
+use crate::changelog::{CHANGELOG_FILE, CHANGELOG_SUMMARY_FILE};
+use crate::checksums::Algorithm;
+use crate::error::{Error, Result};
+use crate::index::INDEX_FILE;
+use crate::signing::{VerifyingKey, PUBLIC_KEY_FILE, SIGNATURE_EXTENSION};
+
+pub mod git;
+pub mod local;
+pub mod s3;
+
+/// The future returned by [`OutputSink`] methods.
+pub type SinkFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// A destination for the output of a run.
+pub trait OutputSink: Send + Sync {
+    /// Describes the destination for logging, such as `s3://patches/spigot/`.
+    fn describe(&self) -> String;
+
+    /// Uploads files of the run directory, replacing existing ones.
+    ///
+    /// # Arguments
+    ///
+    /// * `run_dir` - The run directory.
+    /// * `files` - The files, relative to the run directory with `/` as separator.
+    fn upload<'a>(&'a self, run_dir: &'a Path, files: &'a [String]) -> SinkFuture<'a>;
+
+    /// Removes files that were removed from the run directory. Files that are
+    /// not there are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `run_dir` - The run directory.
+    /// * `files` - The files, relative to the run directory with `/` as separator.
+    fn remove<'a>(&'a self, run_dir: &'a Path, files: &'a [String]) -> SinkFuture<'a>;
+
+    /// Completes the run, after all of its files were uploaded.
+    fn finish<'a>(&'a self, _run_dir: &'a Path) -> SinkFuture<'a> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// A sink in the config, selected by its `type`.
+#[serial_snake]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SinkConfig {
+    /// Copies the output to a directory.
+    Local { path: String },
+    /// Uploads the output to an S3-compatible bucket.
+    S3 {
+        /// The endpoint, such as `https://s3.eu-central-1.amazonaws.com` or
+        /// `http://localhost:9000`.
+        endpoint: String,
+        bucket: String,
+        /// Defaults to [`s3::DEFAULT_REGION`].
+        region: Option<String>,
+        /// Prepended to every object key, such as `spigot/`.
+        #[serde(default)]
+        prefix: String,
+        /// Defaults to the `AWS_ACCESS_KEY_ID` environment variable.
+        access_key: Option<String>,
+        /// Defaults to the `AWS_SECRET_ACCESS_KEY` environment variable.
+        secret_key: Option<String>,
+        /// Whether the bucket is addressed as a subdomain of the endpoint instead
+        /// of as the first segment of the path.
+        #[serde(default)]
+        virtual_hosted: bool,
+    },
+    /// Commits the output to a git repository and pushes it.
+    Git {
+        /// The checkout to commit to. Defaults to the run directory itself.
+        path: Option<String>,
+        /// A URL or the name of a remote of the checkout. Nothing is pushed if unset.
+        remote: Option<String>,
+        /// Defaults to [`crate::publish::DEFAULT_BRANCH`].
+        branch: Option<String>,
+    },
+}
+
+impl SinkConfig {
+    /// Creates the configured sink.
+    ///
+    /// # Arguments
+    ///
+    /// * `run_dir` - The run directory.
+    /// * `public_key` - The key git sinks verify the output with before committing.
+    pub fn build(&self, run_dir: &Path, public_key: Option<VerifyingKey>) -> Result<Box<dyn OutputSink>> {
+        Ok(match self {
+            SinkConfig::Local { path } => Box::new(local::LocalSink::new(path)),
+            SinkConfig::S3 {
+                endpoint,
+                bucket,
+                region,
+                prefix,
+                access_key,
+                secret_key,
+                virtual_hosted,
+            } => Box::new(s3::S3Sink::new(
+                endpoint,
+                bucket,
+                region.as_deref(),
+                prefix,
+                access_key.as_deref(),
+                secret_key.as_deref(),
+                *virtual_hosted,
+            )?),
+            SinkConfig::Git { path, remote, branch } => Box::new(git::GitSink::new(
+                path.as_ref().map_or_else(|| run_dir.to_path_buf(), PathBuf::from),
+                remote.clone(),
+                branch.clone(),
+                public_key,
+            )),
+        })
+    }
+}
+
+/// All sinks of a run.
+#[derive(Default)]
+pub struct Sinks(Vec<Box<dyn OutputSink>>);
+
+impl Sinks {
+    pub fn new(sinks: Vec<Box<dyn OutputSink>>) -> Self {
+        Self(sinks)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Uploads files to every sink, in order.
+    pub async fn upload(&self, run_dir: &Path, files: &[String]) -> Result<()> {
+        for sink in &self.0 {
+            sink.upload(run_dir, files).await?;
+            info!("Uploaded {} files to {}", files.len(), sink.describe());
+        }
+
+        Ok(())
+    }
+
+    /// Removes files from every sink, in order.
+    pub async fn remove(&self, run_dir: &Path, files: &[String]) -> Result<()> {
+        if files.is_empty() {
+            return Ok(());
+        }
+        for sink in &self.0 {
+            sink.remove(run_dir, files).await?;
+            info!("Removed {} files from {}", files.len(), sink.describe());
+        }
+
+        Ok(())
+    }
+
+    /// Completes the run on every sink, in order.
+    pub async fn finish(&self, run_dir: &Path) -> Result<()> {
+        for sink in &self.0 {
+            sink.finish(run_dir).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The files in the run directory among `candidates`, which may not all exist.
+fn existing_files<I: IntoIterator<Item = String>>(run_dir: &Path, candidates: I) -> Vec<String> {
+    candidates
+        .into_iter()
+        .filter(|name| run_dir.join(name).is_file())
+        .collect()
+}
+
+/// The output files of a version, and of its build archived in `archived`, if
+/// any, relative to the run directory. The metadata comes last, so it never
+/// refers to files that are not uploaded yet.
+pub fn version_files(run_dir: &Path, version: &str, archived: Option<&Path>) -> Result<Vec<String>> {
+    let mut files = vec![];
+    if let Some(archived) = archived {
+        for file in fs::read_dir(archived).map_err(Error::io(archived))? {
+            let path = file.map_err(Error::io(archived))?.path();
+            if let Ok(name) = path.strip_prefix(run_dir) {
+                let parts = name.iter().map(|part| part.to_string_lossy()).collect::<Vec<_>>();
+                files.push(parts.join("/"));
+            }
+        }
+    }
+
+    files.extend(existing_files(
+        run_dir,
+        [
+            format!("{version}.patch"),
+            format!("{version}.libs"),
+            format!("{version}.libraries.json"),
+            format!("{version}.json.{SIGNATURE_EXTENSION}"),
+            format!("{version}.json"),
+        ],
+    ));
+
+    Ok(files)
+}
+
+/// The output files written at the end of a run, relative to the run directory.
+pub fn run_files(run_dir: &Path) -> Vec<String> {
+    let signed = [INDEX_FILE.to_owned()]
+        .into_iter()
+        .chain(Algorithm::ALL.map(|algorithm| algorithm.manifest_file().to_owned()))
+        .flat_map(|name| [format!("{name}.{SIGNATURE_EXTENSION}"), name]);
+    let unsigned = [PUBLIC_KEY_FILE, CHANGELOG_FILE, CHANGELOG_SUMMARY_FILE].map(str::to_owned);
+
+    existing_files(run_dir, signed.chain(unsigned))
+}
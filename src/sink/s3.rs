@@ -0,0 +1,322 @@
+//! Uploads the output to an S3-compatible bucket, such as AWS S3 or MinIO.
+//!
+//! Objects are uploaded with single `PUT` requests and removed with `DELETE`
+//! requests, signed with AWS Signature Version 4.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{Method, StatusCode, Url};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::config::CONFIG_FILE;
+use crate::error::{Error, Result};
+use crate::http::{self, HttpError};
+use crate::sink::{OutputSink, SinkFuture};
+use crate::util::content_type;
+
+/// The region requests are signed for if none is configured. MinIO and most
+/// other S3-compatible stores accept it.
+pub const DEFAULT_REGION: &str = "us-east-1";
+
+const SERVICE: &str = "s3";
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+
+/// An S3-compatible bucket.
+pub struct S3Sink {
+    endpoint: Url,
+    bucket: String,
+    region: String,
+    prefix: String,
+    credentials: Credentials,
+    virtual_hosted: bool,
+}
+
+/// An access key and its secret.
+#[derive(Clone, Debug)]
+pub struct Credentials {
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+impl S3Sink {
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint, such as `http://localhost:9000`.
+    /// * `bucket` - The bucket.
+    /// * `region` - The region, [`DEFAULT_REGION`] if unset.
+    /// * `prefix` - Prepended to every object key.
+    /// * `access_key` - The access key, `AWS_ACCESS_KEY_ID` if unset.
+    /// * `secret_key` - The secret key, `AWS_SECRET_ACCESS_KEY` if unset.
+    /// * `virtual_hosted` - Whether the bucket is a subdomain of the endpoint.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the endpoint is no URL or the credentials are missing.
+    pub fn new(
+        endpoint: &str,
+        bucket: &str,
+        region: Option<&str>,
+        prefix: &str,
+        access_key: Option<&str>,
+        secret_key: Option<&str>,
+        virtual_hosted: bool,
+    ) -> Result<Self> {
+        let endpoint = Url::parse(endpoint)
+            .map_err(|e| Error::config(CONFIG_FILE, format!("invalid S3 endpoint {endpoint}: {e}")))?;
+        let credential = |value: Option<&str>, variable: &str| {
+            value
+                .map(str::to_owned)
+                .or_else(|| env::var(variable).ok())
+                .ok_or_else(|| Error::config(CONFIG_FILE, format!("S3 sink {bucket} needs {variable}")))
+        };
+
+        Ok(Self {
+            endpoint,
+            bucket: bucket.to_owned(),
+            region: region.unwrap_or(DEFAULT_REGION).to_owned(),
+            prefix: prefix.to_owned(),
+            credentials: Credentials {
+                access_key: credential(access_key, "AWS_ACCESS_KEY_ID")?,
+                secret_key: credential(secret_key, "AWS_SECRET_ACCESS_KEY")?,
+            },
+            virtual_hosted,
+        })
+    }
+
+    /// The URL of an object.
+    pub fn object_url(&self, name: &str) -> Result<Url> {
+        let key = format!("{}{name}", self.prefix);
+        let mut url = self.endpoint.clone();
+        let invalid = |e: String| Error::config(CONFIG_FILE, format!("invalid S3 endpoint {}: {e}", self.endpoint));
+
+        if self.virtual_hosted {
+            let host = url.host_str().unwrap_or_default();
+            url.set_host(Some(&format!("{}.{host}", self.bucket)))
+                .map_err(|e| invalid(e.to_string()))?;
+        }
+        {
+            let mut segments = url
+                .path_segments_mut()
+                .map_err(|_| invalid("cannot be a base".to_owned()))?;
+            segments.pop_if_empty();
+            if !self.virtual_hosted {
+                segments.push(&self.bucket);
+            }
+            segments.extend(key.split('/'));
+        }
+
+        Ok(url)
+    }
+
+    async fn put(&self, name: &str, body: Vec<u8>) -> Result<()> {
+        self.send(Method::PUT, name, body).await
+    }
+
+    /// Deletes an object. Deleting an object that does not exist succeeds.
+    async fn delete(&self, name: &str) -> Result<()> {
+        self.send(Method::DELETE, name, vec![]).await
+    }
+
+    async fn send(&self, method: Method, name: &str, body: Vec<u8>) -> Result<()> {
+        let url = &self.object_url(name)?;
+        let payload_hash = &hex::encode(Sha256::digest(&body));
+        let method = &method;
+        let body = &body;
+
+        http::retry(url, move || async move {
+            let mut headers = BTreeMap::new();
+            headers.insert("host".to_owned(), host(url));
+            headers.insert("x-amz-content-sha256".to_owned(), payload_hash.clone());
+            headers.insert("x-amz-date".to_owned(), amz_date(Utc::now()));
+            let authorization =
+                authorization(method.as_str(), url, &headers, payload_hash, &self.region, &self.credentials);
+
+            // reqwest sets the host itself.
+            headers.remove("host");
+            let mut request = headers
+                .iter()
+                .fold(http::client()?.request(method.clone(), url.clone()), |request, (name, value)| {
+                    request.header(name, value)
+                })
+                .header(AUTHORIZATION, authorization);
+            if *method == Method::PUT {
+                request = request.header(CONTENT_TYPE, content_type(name)).body(body.clone());
+            }
+
+            let response = request.send().await.map_err(|source| HttpError::Request {
+                url: url.clone(),
+                source,
+            })?;
+            if response.status().is_success()
+                || (*method == Method::DELETE && response.status() == StatusCode::NOT_FOUND)
+            {
+                Ok(())
+            } else {
+                Err(HttpError::Status {
+                    url: url.clone(),
+                    status: response.status(),
+                })
+            }
+        })
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl OutputSink for S3Sink {
+    fn describe(&self) -> String {
+        format!("s3://{}/{}", self.bucket, self.prefix)
+    }
+
+    fn upload<'a>(&'a self, run_dir: &'a Path, files: &'a [String]) -> SinkFuture<'a> {
+        Box::pin(async move {
+            for name in files {
+                let path = run_dir.join(name);
+                let body = fs::read(&path).map_err(Error::io(&path))?;
+                self.put(name, body).await?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn remove<'a>(&'a self, _run_dir: &'a Path, files: &'a [String]) -> SinkFuture<'a> {
+        Box::pin(async move {
+            for name in files {
+                self.delete(name).await?;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+/// The value of the `Host` header of a URL, with the port if it is not the
+/// default one of the scheme.
+fn host(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_owned(),
+    }
+}
+
+/// Formats a time as in the `x-amz-date` header, such as `20130524T000000Z`.
+pub fn amz_date(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
+
+/// Derives the key requests are signed with from the secret key.
+///
+/// # Arguments
+///
+/// * `secret_key` - The secret access key.
+/// * `date` - The date of the request, such as `20130524`.
+/// * `region` - The region.
+/// * `service` - The service, `s3` for S3.
+pub fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> [u8; 32] {
+    [region, service, "aws4_request"].iter().fold(
+        hmac_sha256(format!("AWS4{secret_key}").as_bytes(), date.as_bytes()),
+        |key, part| hmac_sha256(&key, part.as_bytes()),
+    )
+}
+
+/// Percent-encodes everything but unreserved characters and `/`, as required
+/// for the canonical URI of S3 requests.
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Computes the `Authorization` header of a request with AWS Signature
+/// Version 4.
+///
+/// # Arguments
+///
+/// * `method` - The HTTP method.
+/// * `url` - The URL, without a query.
+/// * `headers` - The headers to sign by lowercase name, including `host` and
+///   `x-amz-date`.
+/// * `payload_hash` - The hex encoded SHA-256 hash of the body.
+/// * `region` - The region.
+/// * `credentials` - The credentials.
+pub fn authorization(
+    method: &str,
+    url: &Url,
+    headers: &BTreeMap<String, String>,
+    payload_hash: &str,
+    region: &str,
+    credentials: &Credentials,
+) -> String {
+    // The path of a `Url` is already percent-encoded; decode it so every
+    // character is encoded exactly once.
+    let path = percent_decode(url.path());
+    let canonical_headers = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect::<String>();
+    let signed_headers = headers.keys().map(String::as_str).collect::<Vec<_>>().join(";");
+    let canonical_request = format!(
+        "{method}\n{}\n{}\n{canonical_headers}\n{signed_headers}\n{payload_hash}",
+        encode_path(&path),
+        url.query().unwrap_or_default(),
+    );
+
+    let amz_date = headers.get("x-amz-date").map(String::as_str).unwrap_or_default();
+    let date = amz_date.get(..8).unwrap_or_default();
+    let scope = format!("{date}/{region}/{SERVICE}/aws4_request");
+    let string_to_sign = format!(
+        "{ALGORITHM}\n{amz_date}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let key = signing_key(&credentials.secret_key, date, region, SERVICE);
+    let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+
+    format!(
+        "{ALGORITHM} Credential={}/{scope},SignedHeaders={signed_headers},Signature={signature}",
+        credentials.access_key
+    )
+}
+
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match (bytes[i], hex.and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
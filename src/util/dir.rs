@@ -6,8 +6,8 @@ use ctor::dtor;
 use std::env::temp_dir;
 use std::fs::create_dir_all;
 use std::path::{Path, PathBuf};
-use std::{fs, io};
-use std::io::{Error, ErrorKind};
+use std::fs;
+use crate::error::{Error, Result};
 
 /// List of all temporary directories in the running program.
 static mut TEMP_DIRS: Vec<PathBuf> = Vec::new();
@@ -22,9 +22,9 @@ static mut TEMP_DIRS: Vec<PathBuf> = Vec::new();
 /// # Returns
 ///
 /// A result of the created temporary directory.
-pub fn create_temp_dir<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
+pub fn create_temp_dir<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
     let dir = temp_dir().join(path);
-    create_dir_all(&dir)?;
+    create_dir_all(&dir).map_err(Error::io(&dir))?;
     unsafe { TEMP_DIRS.push(dir.clone()); }
 
     Ok(dir)
@@ -38,22 +38,24 @@ pub fn create_temp_dir<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
 ///
 /// # Returns
 ///
-/// A result that is either `Ok(())` if the contents were removed successfully, or `Err(io::Error)`
+/// A result that is either `Ok(())` if the contents were removed successfully, or `Err(Error)`
 /// if an error occurred while removing the contents.
-pub async fn clear_directory<P: AsRef<Path>>(path: P) -> io::Result<()> {
+pub async fn clear_directory<P: AsRef<Path>>(path: P) -> Result<()> {
     let dir = path.as_ref();
     if dir.is_dir() {
-        for entry in fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
+        for entry in fs::read_dir(dir).map_err(Error::io(dir))? {
+            let path = entry.map_err(Error::io(dir))?.path();
             if path.is_dir() {
-                fs::remove_dir_all(&path)?;
+                fs::remove_dir_all(&path).map_err(Error::io(&path))?;
             } else {
-                fs::remove_file(&path)?;
+                fs::remove_file(&path).map_err(Error::io(&path))?;
             }
         }
     } else {
-        Err(Error::new(ErrorKind::InvalidInput, "The provided path is not a directory"))?;
+        return Err(Error::Io {
+            path: dir.to_path_buf(),
+            source: std::io::Error::new(std::io::ErrorKind::InvalidInput, "The provided path is not a directory"),
+        });
     }
     Ok(())
 }
@@ -1,11 +1,14 @@
 //! Module containing utilities.
 
-use std::fs::File;
-use std::io::{self, Read};
+use std::fs::{self, File};
+use std::io;
 use std::path::Path;
 
-use chrono::Local;
+use chrono::{DateTime, Local, SecondsFormat, Utc};
 use sha1::{Digest, Sha1};
+use sha2::{Sha256, Sha512};
+
+use crate::error::{Error, Result};
 use tracing_subscriber::fmt::format::Writer;
 use tracing_subscriber::fmt::time::FormatTime;
 
@@ -19,13 +22,73 @@ impl FormatTime for TimeFormatter {
     }
 }
 
-pub fn sha1<P: AsRef<Path>>(path: P) -> io::Result<String> {
-    let mut bytes = vec![];
-    let mut file = File::open(path)?;
-    file.read_to_end(&mut bytes)?;
+/// Formats a time as an RFC 3339 timestamp in UTC, such as `2024-11-02T13:37:00Z`.
+pub fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Computes the SHA-1 hash of a file.
+///
+/// # Returns
+///
+/// The lowercase hex encoded hash.
+pub fn sha1<P: AsRef<Path>>(path: P) -> Result<String> {
+    let path = path.as_ref();
+    hash_file::<Sha1>(path).map_err(Error::io(path))
+}
+
+/// Computes the SHA-256 hash of a file.
+///
+/// # Returns
+///
+/// The lowercase hex encoded hash.
+pub fn sha256<P: AsRef<Path>>(path: P) -> Result<String> {
+    let path = path.as_ref();
+    hash_file::<Sha256>(path).map_err(Error::io(path))
+}
+
+/// Computes the SHA-512 hash of a file.
+///
+/// # Returns
+///
+/// The lowercase hex encoded hash.
+pub fn sha512<P: AsRef<Path>>(path: P) -> Result<String> {
+    let path = path.as_ref();
+    hash_file::<Sha512>(path).map_err(Error::io(path))
+}
 
-    let mut hasher = Sha1::new();
-    hasher.update(bytes);
+/// Hashes a file with any [`Digest`] without reading it into memory at once.
+pub(crate) fn hash_file<D: Digest + io::Write>(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = D::new();
+    io::copy(&mut file, &mut hasher)?;
 
     Ok(hex::encode(hasher.finalize()))
 }
+
+/// The media type of an output file, by its name.
+pub fn content_type(name: &str) -> &'static str {
+    if name.ends_with(".sig") || name.ends_with("SUMS") {
+        return "text/plain; charset=utf-8";
+    }
+
+    match Path::new(name).extension().and_then(|extension| extension.to_str()) {
+        Some("json") => "application/json",
+        Some("md") => "text/markdown; charset=utf-8",
+        Some("libs" | "pub") => "text/plain; charset=utf-8",
+        Some("jar") => "application/java-archive",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Writes a file by writing a temporary file next to it and renaming it, so
+/// readers never see a partially written file.
+pub fn write_atomic<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> Result<()> {
+    let path = path.as_ref();
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp = path.with_file_name(temp_name);
+
+    fs::write(&temp, contents).map_err(Error::io(&temp))?;
+    fs::rename(&temp, path).map_err(Error::io(path))
+}
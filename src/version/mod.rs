@@ -3,11 +3,13 @@
 pub mod schema;
 
 use crate::version::schema::piston::{PistonVersion, PistonVersionMeta, PistonVersionsResponse};
-use crate::{download_url, fetch_url, get_url, Reqwsult};
+use crate::error::{Error, Result};
+use crate::{download_url, fetch_url, get_url};
 use regex::Regex;
 use schema::spigot::SpigotVersionMeta;
 use scraper::{Html, Selector};
 use std::path::Path;
+use std::sync::LazyLock;
 use tracing::warn;
 
 /// The URL which should be used for fetching SpigotMC versions.
@@ -19,6 +21,11 @@ const VERSION_REGEX: &str = r"^1\.\d{1,2}(?:\.\d{1,2})?$";
 /// The URL which should be used for fetching Piston Metadata.
 const PISTON_META_URL: &str = "https://piston-meta.mojang.com/mc/game/version_manifest_v2.json";
 
+static VERSION_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(VERSION_REGEX).expect("VERSION_REGEX is a valid regex"));
+
+static LINK_SELECTOR: LazyLock<Selector> =
+    LazyLock::new(|| Selector::parse("a").expect("`a` is a valid selector"));
 
 /// Function to filter out versions from an HTML page.
 ///
@@ -29,15 +36,12 @@ const PISTON_META_URL: &str = "https://piston-meta.mojang.com/mc/game/version_ma
 ///
 /// The filtered list of versions, based off the [`VERSION_REGEX`] and JSON files.
 pub fn filter_versions(document: Html) -> Vec<String> {
-    let version_regex = Regex::new(VERSION_REGEX).unwrap();
-    let a_selector = Selector::parse("a").unwrap();
-
     let mut list: Vec<String> = Vec::new();
-    for element in document.select(&a_selector) {
+    for element in document.select(&LINK_SELECTOR) {
         if let Some(ref_href) = element.value().attr("href") {
             let href = ref_href.strip_suffix(".json").unwrap_or(ref_href);
 
-            if version_regex.is_match(href) {
+            if VERSION_PATTERN.is_match(href) {
                 list.push(href.to_string());
             }
         }
@@ -50,51 +54,42 @@ pub fn filter_versions(document: Html) -> Vec<String> {
 ///
 /// # Returns
 /// All SpigotMC versions.
-pub async fn fetch_versions() -> Vec<String> {
-    filter_versions(fetch_url(VERSIONS_URL).await.unwrap_or_else(|err| {
-        panic!("failed fetching versions: {err:#?}")
-    }))
+pub async fn fetch_versions() -> Result<Vec<String>> {
+    Ok(filter_versions(fetch_url(VERSIONS_URL).await?))
 }
 
-pub async fn fetch_piston_meta() -> Reqwsult<PistonVersionsResponse> {
+pub async fn fetch_piston_meta() -> Result<PistonVersionsResponse> {
     let text = get_url(PISTON_META_URL).await?;
-    Ok(serde_json::from_str(&text).unwrap_or_else(|err| {
-        panic!("failed deserializing piston meta: {err:#?}")
-    }))
+    serde_json::from_str(&text).map_err(Error::metadata("piston version manifest"))
 }
 
-pub async fn fetch_piston_version_meta(versions: PistonVersionsResponse, version: String) -> Reqwsult<PistonVersionMeta> {
-    let version = versions
-        .versions
-        .iter()
-        .find(|ver| ver.id == version)
-        .unwrap_or_else(|| {
-            warn!("failed to find version {version}, falling back to latest: {}", versions.latest.release);
-            versions
-                .versions
-                .iter()
-                .find(|ver| ver.id == versions.latest.release)
-                .unwrap()
-        })
-        .clone();
-
-    Ok(
-        serde_json::from_str(&(get_url(version.url).await?)).unwrap_or_else(|err| {
-            panic!("failed deserializing version: {err:#?}")
-        })
-    )
+/// Fetches the Piston metadata of a specific version.
+///
+/// # Arguments
+/// * `versions` - The Piston version manifest, see [`fetch_piston_meta`].
+/// * `version` - The id of the version.
+///
+/// # Returns
+///
+/// The version's metadata, or `None` if Mojang does not list the version.
+pub async fn fetch_piston_version_meta(versions: &PistonVersionsResponse, version: &str) -> Result<Option<PistonVersionMeta>> {
+    let Some(version) = versions.versions.iter().find(|ver| ver.id == version) else {
+        warn!("failed to find version {version} in the piston manifest");
+        return Ok(None);
+    };
+
+    serde_json::from_str(&(get_url(&version.url).await?))
+        .map(Some)
+        .map_err(Error::metadata(format!("piston metadata of {}", version.id)))
 }
 
-pub async fn download_version<P: AsRef<Path>>(version: PistonVersion, path: P) -> Reqwsult<()> {
+pub async fn download_version<P: AsRef<Path>>(version: PistonVersion, path: P) -> Result<()> {
     download_url(version.url, path).await
 }
 
-pub async fn fetch_spigot_version_meta(version: String) -> Reqwsult<SpigotVersionMeta> {
+pub async fn fetch_spigot_version_meta(version: String) -> Result<SpigotVersionMeta> {
     let url = format!("{VERSIONS_URL}/{version}.json");
 
-    Ok(
-        serde_json::from_str(&(get_url(url).await?)).unwrap_or_else(|err| {
-            panic!("failed deserializing version meta: {err:#?}")
-        })
-    )
+    serde_json::from_str(&(get_url(url).await?))
+        .map_err(Error::metadata(format!("spigot metadata of {version}")))
 }
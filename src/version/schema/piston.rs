@@ -22,13 +22,15 @@ pub struct PistonVersionsResponse {
 
 #[serial]
 pub struct PistonVersionDownload {
+    pub sha1: String,
+    pub size: u64,
     pub url: String,
 }
 
 #[serial_snake]
 pub struct PistonVersionDownloads {
     pub server: PistonVersionDownload,
-    pub server_mappings: PistonVersionDownload,
+    pub server_mappings: Option<PistonVersionDownload>,
 }
 
 #[serial]
@@ -0,0 +1,340 @@
+//! Notifies webhooks about the results of a run.
+//!
+//! Every configured webhook gets an event when a version was built, when a
+//! version failed, and a summary when the run ends, either as generic JSON or
+//! as a Discord message. Delivery is retried like any other request, and a
+//! webhook that cannot be reached never fails the run.
+
+use std::time::Instant;
+
+use proc_macros::{serial, serial_snake};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Url;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::changelog::{ChangeKind, Changelog, RefChange, VersionChange};
+use crate::config::PatchedVersionMeta;
+use crate::error::{Error, Result};
+use crate::http::{self, HttpError};
+use crate::version::schema::spigot::SpigotVersionRefs;
+
+/// The name Discord messages are sent as.
+const DISCORD_USERNAME: &str = "bin-patch-gen";
+
+/// How many characters of an error Discord messages include.
+const MAX_DISCORD_ERROR_LENGTH: usize = 1000;
+
+const DISCORD_GREEN: u32 = 0x2ecc71;
+const DISCORD_BLUE: u32 = 0x3498db;
+const DISCORD_RED: u32 = 0xe74c3c;
+
+/// A webhook in the config.
+#[serial_snake]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub format: WebhookFormat,
+    /// The events sent to the webhook. All are sent if empty.
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+}
+
+/// The payload format of a webhook.
+#[serial_snake]
+#[derive(Default, Copy, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// The [`Event`] itself.
+    #[default]
+    Json,
+    /// A Discord message with an embed.
+    Discord,
+}
+
+/// The kinds of [`Event`].
+#[serial_snake]
+#[derive(Copy, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Built,
+    Failed,
+    Summary,
+}
+
+/// A version was built and its patch verified.
+#[serial]
+pub struct VersionBuilt {
+    pub version: String,
+    pub kind: ChangeKind,
+    pub refs: SpigotVersionRefs,
+    /// The refs that moved since the previous build.
+    pub changes: Vec<RefChange>,
+    pub patch_hash: String,
+    pub vanilla_jar_hash: String,
+    pub patched_jar_hash: String,
+    pub patch_sha256: Option<String>,
+}
+
+/// A version failed to build.
+#[serial]
+pub struct VersionFailed {
+    pub version: String,
+    pub error: String,
+}
+
+/// The end of a run.
+#[serial]
+pub struct RunSummary {
+    /// The changelog subject, such as `Update 1.21.3; add 1.21.4`.
+    pub subject: String,
+    /// The versions built, in order.
+    pub built: Vec<String>,
+    /// The versions that failed, in order.
+    pub failed: Vec<String>,
+    /// Why the run failed, if it did.
+    pub error: Option<String>,
+    pub duration_secs: u64,
+}
+
+/// An event sent to webhooks, tagged with its kind as `event`.
+#[serial]
+#[serde(tag = "event")]
+pub enum Event {
+    Built(VersionBuilt),
+    Failed(VersionFailed),
+    Summary(RunSummary),
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::Built(_) => EventKind::Built,
+            Event::Failed(_) => EventKind::Failed,
+            Event::Summary(_) => EventKind::Summary,
+        }
+    }
+
+    /// The event as a Discord message.
+    pub fn to_discord(&self) -> Value {
+        let (title, description, color, fields) = match self {
+            Event::Built(built) => {
+                let (verb, color) = match built.kind {
+                    ChangeKind::Added => ("Added", DISCORD_GREEN),
+                    ChangeKind::Updated => ("Updated", DISCORD_BLUE),
+                    ChangeKind::Rebuilt => ("Rebuilt", DISCORD_BLUE),
+                };
+                let changes = built
+                    .changes
+                    .iter()
+                    .map(|change| match &change.from {
+                        Some(from) => format!("{}: `{}` → `{}`", change.repository, short(from), short(&change.to)),
+                        None => format!("{}: `{}`", change.repository, short(&change.to)),
+                    })
+                    .collect::<Vec<_>>();
+                let fields = vec![
+                    json!({"name": "Patched jar SHA-1", "value": format!("`{}`", built.patched_jar_hash)}),
+                    json!({"name": "Patch SHA-1", "value": format!("`{}`", built.patch_hash)}),
+                ];
+                (format!("{verb} {}", built.version), changes.join("\n"), color, fields)
+            }
+            Event::Failed(failed) => (
+                format!("Failed building {}", failed.version),
+                code_block(&failed.error),
+                DISCORD_RED,
+                vec![],
+            ),
+            Event::Summary(summary) => {
+                let mut lines = vec![];
+                if !summary.built.is_empty() {
+                    lines.push(format!("Built {}", summary.built.join(", ")));
+                }
+                if !summary.failed.is_empty() {
+                    lines.push(format!("Failed building {}", summary.failed.join(", ")));
+                }
+                if let Some(error) = &summary.error {
+                    lines.push(code_block(error));
+                }
+                lines.push(format!("Took {}s", summary.duration_secs));
+
+                let (title, color) = match summary.error {
+                    Some(_) => ("Run failed".to_owned(), DISCORD_RED),
+                    None if !summary.failed.is_empty() => (summary.subject.clone(), DISCORD_RED),
+                    None => (summary.subject.clone(), DISCORD_GREEN),
+                };
+                (title, lines.join("\n"), color, vec![])
+            }
+        };
+
+        json!({
+            "username": DISCORD_USERNAME,
+            "embeds": [{
+                "title": title,
+                "description": description,
+                "color": color,
+                "fields": fields,
+            }],
+        })
+    }
+}
+
+/// The first 10 characters of a commit hash.
+fn short(hash: &str) -> &str {
+    hash.get(..10).unwrap_or(hash)
+}
+
+fn code_block(error: &str) -> String {
+    let error = match error.char_indices().nth(MAX_DISCORD_ERROR_LENGTH) {
+        Some((end, _)) => format!("{}…", &error[..end]),
+        None => error.to_owned(),
+    };
+    format!("```\n{}\n```", error.replace("```", "'''"))
+}
+
+impl WebhookConfig {
+    /// Whether the webhook gets events of a kind.
+    pub fn accepts(&self, kind: EventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+
+    /// Posts an event to the webhook, retrying transient failures.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the URL is invalid, the event cannot be serialized or the
+    /// webhook does not respond with success. Errors only name the origin of
+    /// the URL, since webhook URLs usually contain a secret.
+    pub async fn send(&self, event: &Event) -> Result<()> {
+        let url = http::parse_url(self.url.as_str()).map_err(|err| match err {
+            HttpError::InvalidUrl(err) => HttpError::InvalidUrl(err.without_url()),
+            err => err,
+        })?;
+        let origin = Url::parse(&url.origin().ascii_serialization()).unwrap_or_else(|_| url.clone());
+        let payload = match self.format {
+            WebhookFormat::Json => serde_json::to_vec(event),
+            WebhookFormat::Discord => serde_json::to_vec(&event.to_discord()),
+        }
+        .map_err(Error::metadata("webhook payload"))?;
+
+        let (url, origin, payload) = (&url, &origin, &payload);
+        http::retry(origin, move || async move {
+            let response = http::client()?
+                .post(url.clone())
+                .header(CONTENT_TYPE, "application/json")
+                .body(payload.clone())
+                .send()
+                .await
+                .map_err(|source| HttpError::Request {
+                    url: origin.clone(),
+                    source: source.without_url(),
+                })?;
+
+            if response.status().is_success() {
+                Ok(())
+            } else {
+                Err(HttpError::Status {
+                    url: origin.clone(),
+                    status: response.status(),
+                })
+            }
+        })
+        .await
+        .map_err(Error::from)
+    }
+}
+
+/// Sends the events of a run to the configured webhooks.
+pub struct Notifier {
+    webhooks: Vec<WebhookConfig>,
+    started: Instant,
+    /// The version being built.
+    building: Option<String>,
+    changes: Vec<VersionChange>,
+    /// The versions that failed.
+    failed: Vec<String>,
+}
+
+impl Notifier {
+    pub fn new(webhooks: Vec<WebhookConfig>) -> Self {
+        Self {
+            webhooks,
+            started: Instant::now(),
+            building: None,
+            changes: vec![],
+            failed: vec![],
+        }
+    }
+
+    /// Sends an event to every webhook that accepts it.
+    pub async fn send(&self, event: Event) {
+        for webhook in self.webhooks.iter().filter(|webhook| webhook.accepts(event.kind())) {
+            match webhook.send(&event).await {
+                Ok(()) => info!("Sent {:?} event to a webhook", event.kind()),
+                Err(err) => warn!("Failed sending {:?} event to a webhook: {err}", event.kind()),
+            }
+        }
+    }
+
+    /// Starts building a version. An error before the next version starts is
+    /// reported as a failure of this version.
+    pub fn start(&mut self, version: &str) {
+        self.building = Some(version.to_owned());
+    }
+
+    /// Skips the version that was started, because it is up to date.
+    pub fn skip(&mut self) {
+        self.building = None;
+    }
+
+    /// Reports the version that was started as built.
+    pub async fn built(&mut self, change: &VersionChange, meta: &PatchedVersionMeta) {
+        self.building = None;
+        self.changes.push(change.clone());
+        self.send(Event::Built(VersionBuilt {
+            version: change.version.clone(),
+            kind: change.kind,
+            refs: meta.commit_hashes.clone(),
+            changes: change.refs.clone(),
+            patch_hash: meta.patch_hash.clone(),
+            vanilla_jar_hash: meta.vanilla_jar_hash.clone(),
+            patched_jar_hash: meta.patched_jar_hash.clone(),
+            patch_sha256: meta.patch_checksums.as_ref().map(|checksums| checksums.sha256.clone()),
+        }))
+        .await;
+    }
+
+    /// Reports the version that was started as failed.
+    pub async fn failed(&mut self, error: &Error) {
+        let Some(version) = self.building.take() else {
+            return;
+        };
+        self.send(Event::Failed(VersionFailed {
+            version: version.clone(),
+            error: error.to_string(),
+        }))
+        .await;
+        self.failed.push(version);
+    }
+
+    /// Reports the end of a run: the version that was started, if the run
+    /// failed while building it, and the summary.
+    ///
+    /// # Arguments
+    ///
+    /// * `error` - Why the run failed, if it did.
+    pub async fn finish(mut self, error: Option<&Error>) {
+        if let Some(error) = error {
+            self.failed(error).await;
+        }
+
+        let built = self.changes.iter().map(|change| change.version.clone()).collect();
+        self.send(Event::Summary(RunSummary {
+            subject: Changelog::new(self.changes.clone()).subject(),
+            built,
+            failed: self.failed.clone(),
+            error: error.map(ToString::to_string),
+            duration_secs: self.started.elapsed().as_secs(),
+        }))
+        .await;
+    }
+}
@@ -8,12 +8,19 @@ git checkout release
 git branch --set-upstream-to=origin/release release
 git pull
 
-cat >config.toml <<EOF
+if [[ ! -f "config.toml" ]]; then
+  cat >config.toml <<EOF
 java_8_home = ""
 java_16_home = ""
 java_17_home = ""
 java_21_home = ""
+daemon_health_address = "0.0.0.0:8080"
+
+[[sinks]]
+type = "git"
+remote = "origin"
 EOF
+fi
 
 if [[ ! -d "work/.git" ]]; then
   mkdir -p work
@@ -28,24 +35,5 @@ cd work
 git pull origin master
 cd ..
 
-# check if docker container exists
-if docker container inspect sploon-bin-patch-gen > /dev/null 2>&1; then
-  # if it does, check if it's running
-  if [ "$(docker container inspect -f '{{.State.Status}}' sploon-bin-patch-gen)" != "running" ]; then
-    docker compose down
-    docker compose up gen
-
-    cd work || exit 1
-    git add .
-    git commit -am "automated update"
-    git push -u origin master
-  fi
-else
-  docker compose up gen
-
-  cd work || exit 1
-  git add .
-  git commit -am "automated update"
-  git push -u origin master
-fi
-
+# (re)start the daemon, which polls Spigot, builds changed versions and publishes them
+docker compose up -d --build daemon